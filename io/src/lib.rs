@@ -4,6 +4,14 @@ use gmeta::{In, InOut, Metadata};
 use gstd::{errors::Error as GstdError, prelude::*, ActorId};
 
 pub type TransactionId = u64;
+pub type PoolId = u64;
+pub type PositionId = u64;
+
+/// The multiplier that leaves a stake's weight unchanged, in basis points.
+pub const BASIS_POINTS: u32 = 10_000;
+
+/// The scale of the vault's price per share.
+pub const PRICE_SCALE: u128 = 10_u128.pow(18);
 
 pub struct StakingMetadata;
 
@@ -22,47 +30,579 @@ pub struct InitStaking {
     pub reward_token_address: ActorId,
     pub distribution_time: u64,
     pub reward_total: u128,
+    pub curve: EmissionCurve,
+    pub staking_token_kind: TokenKind,
+    pub reward_token_kind: TokenKind,
+}
+
+/// How the program moves the token
+#[derive(Debug, Default, Clone, Copy, Decode, Encode, TypeInfo, PartialEq, Eq)]
+pub enum TokenKind {
+    /// The sharded fungible token
+    #[default]
+    Sharded,
+    /// The native value attached to the messages, addressed by the zero address
+    Native,
+    /// The gear fungible token, which isn't sharded and moves the tokens with `FTAction::Transfer`
+    Fungible,
+}
+
+/// The shape of the emission within the distribution time
+/// Every curve produces the whole reward by the end of the distribution time
+#[derive(Debug, Default, Clone, Decode, Encode, TypeInfo, PartialEq, Eq)]
+pub enum EmissionCurve {
+    #[default]
+    Linear,
+    /// The emission rate halves continuously every `half_life`
+    ExponentialDecay { half_life: u64 },
+    /// The emission rate halves at the end of every `period`
+    Halving { period: u64 },
+    /// The offsets from the start of the distribution and the relative rates starting at them
+    Piecewise(Vec<(u64, u128)>),
+}
+
+/// A transferable stake held in a pool with positions enabled
+/// The stake is kept under the synthetic staker derived from the position id
+#[derive(Debug, Encode, Decode, TypeInfo, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub pool: PoolId,
+    pub owner: ActorId,
+}
+
+/// The staker holding the stake of the position in its pool
+pub fn position_key(id: PositionId) -> ActorId {
+    let mut key = [0; 32];
+    key[..8].copy_from_slice(b"position");
+    key[24..].copy_from_slice(&id.to_be_bytes());
+
+    key.into()
+}
+
+/// The staker holding the stake of the pool's vault
+pub fn vault_key() -> ActorId {
+    let mut key = [0; 32];
+    key[..5].copy_from_slice(b"vault");
+
+    key.into()
+}
+
+/// Whether the account is one of the stakers kept by the program itself: a position or the vault
+pub fn is_reserved(account: &ActorId) -> bool {
+    let key: &[u8] = account.as_ref();
+
+    *account == vault_key()
+        || (key[..8] == *b"position" && key[8..24].iter().all(|byte| *byte == 0))
+}
+
+/// The operations of the position owner, matching the staker's own actions
+#[derive(Debug, Clone, Decode, Encode, TypeInfo, PartialEq, Eq)]
+pub enum PositionAction {
+    Withdraw { amount: u128 },
+    RequestUnstake { amount: u128 },
+    ClaimUnbonded,
+    Rebond { index: u32 },
+    Compound,
+    GetReward { token: Option<ActorId> },
+    EmergencyWithdraw,
 }
 
 #[derive(Debug, Default, Encode, Decode, TypeInfo, Clone, PartialEq)]
 pub struct Staker {
     pub balance: u128,
+    pub staked_at: u64,
+    pub effective_balance: u128,
+    /// The weight of the staker's tier in basis points, zero outside the tiers
+    pub tier_weight: u32,
+    /// The start of the continuous staking, reduced by withdrawals in proportion
+    pub since: u64,
+    /// The loyalty multiplier in basis points applied at the last interaction, zero if disabled
+    pub loyalty_weight: u32,
+    pub locks: Vec<Lock>,
+    pub unbonding: Vec<UnbondingChunk>,
+    pub rewards: BTreeMap<ActorId, StakerReward>,
+    /// The staker's share of the penalties distributed to the stakers in the staking token
+    pub penalty_reward: StakerReward,
+}
+
+#[derive(Debug, Default, Encode, Decode, TypeInfo, Clone, PartialEq, Eq)]
+pub struct StakerReward {
     pub reward_allowed: u128,
     pub reward_debt: u128,
     pub distributed: u128,
 }
 
+#[derive(Debug, Encode, Decode, TypeInfo, Clone, PartialEq, Eq)]
+pub struct Lock {
+    pub amount: u128,
+    pub unlock_time: u64,
+}
+
+#[derive(Debug, Encode, Decode, TypeInfo, Clone, PartialEq, Eq)]
+pub struct UnbondingChunk {
+    pub amount: u128,
+    pub maturity: u64,
+}
+
+/// A reward scheduled to be produced evenly from `start` to `end`
+#[derive(Debug, Encode, Decode, TypeInfo, Clone, PartialEq, Eq)]
+pub struct Epoch {
+    pub start: u64,
+    pub end: u64,
+    pub amount: u128,
+}
+
+/// Reward tokens of one kind held by the program for all pools
+#[derive(Debug, Default, Encode, Decode, TypeInfo, Clone, Copy, PartialEq, Eq)]
+pub struct RewardFunds {
+    /// Tokens moved in with `FundRewards`
+    pub funded: u128,
+    /// Tokens promised by the reward schedules
+    pub allocated: u128,
+    /// Tokens paid to the stakers
+    pub claimed: u128,
+}
+
+/// The multiplier that grows by `step_bonus` basis points every `step` of continuous staking
+/// up to `max_bonus` basis points over the base weight
+#[derive(Debug, Clone, Decode, Encode, TypeInfo, PartialEq, Eq)]
+pub struct LoyaltyConfig {
+    pub step: u64,
+    pub step_bonus: u32,
+    pub max_bonus: u32,
+}
+
+#[derive(Debug, Clone, Decode, Encode, TypeInfo, PartialEq, Eq)]
+pub struct PenaltyConfig {
+    pub rate: u32,
+    pub holding_period: u64,
+    pub destination: PenaltyDestination,
+}
+
+#[derive(Debug, Clone, Copy, Decode, Encode, TypeInfo, PartialEq, Eq)]
+pub enum PenaltyDestination {
+    Treasury(ActorId),
+    Stakers,
+}
+
+#[derive(Debug, Default, Clone, Copy, Decode, Encode, TypeInfo, PartialEq, Eq)]
+pub enum ForfeitDestination {
+    #[default]
+    Stakers,
+    Owner,
+}
+
+/// Roles allowing to perform the owner's operations, the owner has all of them
+#[derive(Debug, Clone, Copy, Decode, Encode, TypeInfo, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Creates pools, configures them and manages the other roles
+    Admin,
+    /// Manages the reward schedules
+    RewardManager,
+    /// Pauses and unpauses actions
+    Pauser,
+    /// Configures the withdrawal penalties
+    Slasher,
+}
+
+/// Groups of actions that can be paused independently
+/// The position actions are paused along with the staker's own ones,
+/// and `Compound` is paused along with either the stakes or the claims
+#[derive(Debug, Default, Clone, Copy, Decode, Encode, TypeInfo, PartialEq, Eq)]
+pub struct ActionMask {
+    /// `Stake`, `StakeLocked`, `StakeFor`, `VaultDeposit` and `Rebond`
+    pub stake: bool,
+    /// `Withdraw`, `RequestUnstake`, `ClaimUnbonded`, `TransferStake`, `VaultWithdraw` and `SettleOwed`
+    pub withdraw: bool,
+    /// `GetReward`, `GetRewardTo`, `ClaimFor`, `ClaimForfeited`, `TransferPosition` and `Compound`
+    pub claim: bool,
+    /// `CreatePool`, `UpdateStaking`, `NotifyRewardAmount`, `SetFixedApr`, `AddEpoch` and `CancelEpoch`
+    pub update: bool,
+    /// `EmergencyWithdraw`
+    pub emergency_withdraw: bool,
+}
+
+impl ActionMask {
+    /// The groups paused in either of the masks
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            stake: self.stake || other.stake,
+            withdraw: self.withdraw || other.withdraw,
+            claim: self.claim || other.claim,
+            update: self.update || other.update,
+            emergency_withdraw: self.emergency_withdraw || other.emergency_withdraw,
+        }
+    }
+
+    /// The groups paused in this mask and not in the other one
+    pub fn difference(self, other: Self) -> Self {
+        Self {
+            stake: self.stake && !other.stake,
+            withdraw: self.withdraw && !other.withdraw,
+            claim: self.claim && !other.claim,
+            update: self.update && !other.update,
+            emergency_withdraw: self.emergency_withdraw && !other.emergency_withdraw,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Decode, Encode, TypeInfo, PartialEq, Eq)]
 pub enum StakingAction {
-    Stake(u128),
-    Withdraw(u128),
-    UpdateStaking(InitStaking),
-    GetReward,
+    CreatePool(InitStaking),
+    Stake {
+        pool: PoolId,
+        amount: u128,
+    },
+    StakeLocked {
+        pool: PoolId,
+        amount: u128,
+        term: u64,
+    },
+    StakeFor {
+        pool: PoolId,
+        beneficiary: ActorId,
+        amount: u128,
+        locked_until: Option<u64>,
+    },
+    Compound {
+        pool: PoolId,
+    },
+    Withdraw {
+        pool: PoolId,
+        amount: u128,
+    },
+    RequestUnstake {
+        pool: PoolId,
+        amount: u128,
+    },
+    ClaimUnbonded {
+        pool: PoolId,
+    },
+    TransferStake {
+        pool: PoolId,
+        to: ActorId,
+        amount: u128,
+    },
+    SetShareToken {
+        pool: PoolId,
+        token: Option<ActorId>,
+    },
+    SyncShares {
+        pool: PoolId,
+        account: ActorId,
+    },
+    VaultDeposit {
+        pool: PoolId,
+        amount: u128,
+    },
+    VaultWithdraw {
+        pool: PoolId,
+        shares: u128,
+    },
+    EmergencyWithdraw {
+        pool: PoolId,
+    },
+    Rebond {
+        pool: PoolId,
+        index: u32,
+    },
+    UpdateStaking {
+        pool: PoolId,
+        config: InitStaking,
+    },
+    NotifyRewardAmount {
+        pool: PoolId,
+        token: ActorId,
+        amount: u128,
+    },
+    SetFixedApr {
+        pool: PoolId,
+        token: ActorId,
+        kind: TokenKind,
+        rate: u32,
+        coverage: u64,
+    },
+    AddEpoch {
+        pool: PoolId,
+        token: ActorId,
+        kind: TokenKind,
+        epoch: Epoch,
+    },
+    CancelEpoch {
+        pool: PoolId,
+        token: ActorId,
+        index: u32,
+    },
+    FundRewards {
+        token: ActorId,
+        kind: TokenKind,
+        amount: u128,
+    },
+    SetLockTerms {
+        pool: PoolId,
+        terms: Vec<(u64, u32)>,
+    },
+    SetUnbondingPeriod {
+        pool: PoolId,
+        period: u64,
+    },
+    SetPenalty {
+        pool: PoolId,
+        config: Option<PenaltyConfig>,
+    },
+    SetForfeitDestination {
+        pool: PoolId,
+        destination: ForfeitDestination,
+    },
+    ClaimForfeited {
+        pool: PoolId,
+        recipient: ActorId,
+    },
+    SettleOwed {
+        pool: PoolId,
+        account: ActorId,
+    },
+    SetTiers {
+        pool: PoolId,
+        tiers: Vec<(u128, u32)>,
+    },
+    SetLoyalty {
+        pool: PoolId,
+        config: Option<LoyaltyConfig>,
+    },
+    SetPositionMode {
+        pool: PoolId,
+        enabled: bool,
+    },
+    Position {
+        id: PositionId,
+        action: PositionAction,
+    },
+    TransferPosition {
+        id: PositionId,
+        to: ActorId,
+    },
+    /// Pauses the groups of the mask on top of the paused ones
+    Pause(ActionMask),
+    /// Resumes the groups of the mask
+    Unpause(ActionMask),
+    GrantRole {
+        actor: ActorId,
+        role: Role,
+    },
+    RevokeRole {
+        actor: ActorId,
+        role: Role,
+    },
+    TransferOwnership(ActorId),
+    AcceptOwnership,
+    RenounceOwnership,
+    GetReward {
+        pool: PoolId,
+        token: Option<ActorId>,
+    },
+    GetRewardTo {
+        pool: PoolId,
+        token: Option<ActorId>,
+        recipient: ActorId,
+    },
+    ClaimFor {
+        pool: PoolId,
+        staker: ActorId,
+        token: Option<ActorId>,
+    },
+    ApproveClaimer(ActorId),
+    RevokeClaimer(ActorId),
+    SetRewardRecipient(Option<ActorId>),
 }
 
 #[derive(Debug, Encode, Decode, TypeInfo)]
 pub enum StakingEvent {
+    PoolCreated(PoolId),
     StakeAccepted(u128),
+    StakeLocked {
+        amount: u128,
+        unlock_time: u64,
+    },
+    StakedFor {
+        beneficiary: ActorId,
+        amount: u128,
+        locked_until: Option<u64>,
+    },
+    StakeTransferred {
+        to: ActorId,
+        amount: u128,
+    },
+    ShareTokenUpdated(Option<ActorId>),
+    SharesSynced {
+        account: ActorId,
+        balance: u128,
+    },
+    VaultDeposited {
+        amount: u128,
+        shares: u128,
+    },
+    VaultWithdrawn {
+        shares: u128,
+        amount: u128,
+        penalty: u128,
+    },
     Updated,
-    Reward(u128),
-    Withdrawn(u128),
+    RewardNotified {
+        token: ActorId,
+        amount: u128,
+    },
+    FixedAprUpdated,
+    EpochAdded {
+        token: ActorId,
+        epoch: Epoch,
+    },
+    EpochCancelled {
+        token: ActorId,
+        epoch: Epoch,
+    },
+    RewardsFunded {
+        token: ActorId,
+        amount: u128,
+    },
+    LockTermsUpdated,
+    Reward(Vec<(ActorId, u128)>),
+    Compounded {
+        amount: u128,
+    },
+    Withdrawn {
+        amount: u128,
+        penalty: u128,
+    },
+    Unbonding {
+        amount: u128,
+        penalty: u128,
+        maturity: u64,
+    },
+    Rebonded(u128),
+    UnbondingPeriodUpdated,
+    PenaltyUpdated,
+    EmergencyWithdrawn(u128),
+    ForfeitDestinationUpdated,
+    ForfeitedClaimed(Vec<(ActorId, u128)>),
+    OwedSettled {
+        account: ActorId,
+        refund: u128,
+        shares: u128,
+    },
+    TiersUpdated,
+    LoyaltyUpdated,
+    PositionModeUpdated,
+    PositionMinted {
+        id: PositionId,
+        amount: u128,
+    },
+    PositionTransferred {
+        id: PositionId,
+        from: ActorId,
+        to: ActorId,
+    },
+    /// The groups paused after the change
+    Paused(ActionMask),
+    /// The groups left paused after the change
+    Unpaused(ActionMask),
+    RoleGranted {
+        actor: ActorId,
+        role: Role,
+    },
+    RoleRevoked {
+        actor: ActorId,
+        role: Role,
+    },
+    OwnershipTransferStarted(ActorId),
+    OwnershipTransferred(ActorId),
+    OwnershipRenounced,
+    ClaimerApproved(ActorId),
+    ClaimerRevoked(ActorId),
+    RewardRecipientUpdated(Option<ActorId>),
 }
 
 #[derive(Debug, Clone, Default, Encode, Decode, TypeInfo)]
 pub struct IoStaking {
     pub owner: ActorId,
+    pub pending_owner: Option<ActorId>,
+    pub roles: BTreeMap<ActorId, BTreeSet<Role>>,
+    pub funds: BTreeMap<ActorId, RewardFunds>,
+    /// The fixed-APR interest accrued and due within the coverage periods
+    pub liabilities: BTreeMap<ActorId, u128>,
+    /// The operators approved by the stakers to claim their rewards
+    pub claimers: BTreeMap<ActorId, BTreeSet<ActorId>>,
+    pub reward_recipients: BTreeMap<ActorId, ActorId>,
+    pub positions: BTreeMap<PositionId, Position>,
+    pub next_position_id: PositionId,
+    pub token_kinds: BTreeMap<ActorId, TokenKind>,
+    pub pools: Vec<(PoolId, IoPool)>,
+    pub next_pool_id: PoolId,
+    pub transactions: BTreeMap<ActorId, Transaction<StakingAction>>,
+    pub current_tid: TransactionId,
+    pub paused: ActionMask,
+}
+
+#[derive(Debug, Clone, Default, Encode, Decode, TypeInfo)]
+pub struct IoPool {
     pub staking_token_address: ActorId,
-    pub reward_token_address: ActorId,
-    pub tokens_per_stake: u128,
     pub total_staked: u128,
+    pub rewards: Vec<(ActorId, IoReward)>,
+    pub stakers: Vec<(ActorId, Staker)>,
+    pub lock_terms: BTreeMap<u64, u32>,
+    pub lock_expirations: BTreeMap<u64, Vec<(ActorId, u128)>>,
+    pub unbonding_period: u64,
+    pub penalty: Option<PenaltyConfig>,
+    pub unpaid_penalties: u128,
+    /// The penalties distributed per unit of the stakers' weight, scaled like `tokens_per_stake`
+    pub penalty_per_stake: u128,
+    pub forfeit_destination: ForfeitDestination,
+    /// The rewards forfeited to the owner and not claimed yet
+    pub forfeited: BTreeMap<ActorId, u128>,
+    /// The share of the penalties forfeited to the owner, in the staking token
+    pub forfeited_penalties: u128,
+    pub tiers: BTreeMap<u128, u32>,
+    pub loyalty: Option<LoyaltyConfig>,
+    /// Every deposit mints a transferable position instead of crediting the sender
+    pub positions: bool,
+    /// The token minted 1:1 for the stake, making it liquid
+    pub share_token: Option<ActorId>,
+    /// The stake of the shares that left their stakers and wait for the new holders to sync
+    pub detached_shares: u128,
+    /// The staking tokens owed to the depositors whose refund failed
+    pub owed_refunds: BTreeMap<ActorId, u128>,
+    /// The shares owed to the stakers whose shares couldn't be minted back
+    pub owed_shares: BTreeMap<ActorId, u128>,
+    pub vault: IoVault,
+}
+
+/// The auto-compounding vault staking the pool's tokens as a single staker
+/// outside the reward tiers and the loyalty multiplier
+#[derive(Debug, Clone, Default, Encode, Decode, TypeInfo)]
+pub struct IoVault {
+    pub total_shares: u128,
+    /// The vault's stake, the harvested reward included
+    pub total_assets: u128,
+    /// The assets of one share scaled by `PRICE_SCALE`, the pending reward included
+    pub price_per_share: u128,
+    pub shares: Vec<(ActorId, u128)>,
+    /// The average deposit time of each holder's shares, which the early withdrawal penalty runs from
+    pub deposited_at: Vec<(ActorId, u64)>,
+}
+
+#[derive(Debug, Clone, Default, Encode, Decode, TypeInfo)]
+pub struct IoReward {
+    pub tokens_per_stake: u128,
     pub distribution_time: u64,
     pub produced_time: u64,
     pub reward_total: u128,
     pub all_produced: u128,
     pub reward_produced: u128,
-    pub stakers: Vec<(ActorId, Staker)>,
-    pub transactions: BTreeMap<ActorId, Transaction<StakingAction>>,
-    pub current_tid: TransactionId,
+    pub epochs: Vec<Epoch>,
+    pub curve: EmissionCurve,
+    pub apr: u32,
+    pub apr_coverage: u64,
+    pub apr_accrued: u128,
+    pub updated_at: u64,
 }
 
 #[derive(Debug, Clone, Encode, Decode, TypeInfo)]
@@ -75,6 +615,35 @@ pub enum Error {
     InsufficentBalance,
     NotOwner,
     StakerNotFound,
+    PoolNotFound,
+    RewardTokenNotFound,
+    LockedTokens,
+    InvalidLockTerm,
+    LockTermNotFound,
+    UnbondingRequired,
+    UnbondingNotMatured,
+    UnbondingNotFound,
+    InvalidPenalty,
+    Paused,
+    NotPendingOwner,
+    InsufficientFunds,
+    InvalidEpoch,
+    EpochNotFound,
+    EpochStarted,
+    InvalidCurve,
+    InvalidTier,
+    InvalidLoyalty,
+    ZeroAddress,
+    NotClaimer,
+    PoolNotEmpty,
+    PositionNotFound,
+    NotPositionOwner,
+    SelfTransfer,
+    ShareMode,
+    NoShareToken,
+    InvalidTokenKind,
+    InvalidValue,
+    ReservedAddress,
     ContractError(String),
 }
 
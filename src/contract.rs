@@ -4,91 +4,219 @@ use gstd::{errors::Result as GstdResult, exec, msg, prelude::*, ActorId, Message
 use hashbrown::HashMap;
 use staking_io::*;
 
+use crate::token::{self, ShardedToken};
+
 #[derive(Debug, Clone, Default)]
 struct Staking {
     owner: ActorId,
+    pending_owner: Option<ActorId>,
+    roles: BTreeMap<ActorId, BTreeSet<Role>>,
+    funds: BTreeMap<ActorId, RewardFunds>,
+    claimers: BTreeMap<ActorId, BTreeSet<ActorId>>,
+    reward_recipients: BTreeMap<ActorId, ActorId>,
+    positions: BTreeMap<PositionId, Position>,
+    next_position_id: PositionId,
+    token_kinds: BTreeMap<ActorId, TokenKind>,
+    pools: BTreeMap<PoolId, Pool>,
+    next_pool_id: PoolId,
+
+    transactions: BTreeMap<ActorId, Transaction<StakingAction>>,
+    current_tid: TransactionId,
+    paused: ActionMask,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Pool {
     staking_token_address: ActorId,
-    reward_token_address: ActorId,
-    tokens_per_stake: u128,
     total_staked: u128,
+    rewards: BTreeMap<ActorId, Reward>,
+    stakers: HashMap<ActorId, Staker>,
+    lock_terms: BTreeMap<u64, u32>,
+    lock_expirations: BTreeMap<u64, Vec<(ActorId, u128)>>,
+    unbonding_period: u64,
+    penalty: Option<PenaltyConfig>,
+    unpaid_penalties: u128,
+    penalty_per_stake: u128,
+    forfeit_destination: ForfeitDestination,
+    forfeited: BTreeMap<ActorId, u128>,
+    forfeited_penalties: u128,
+    tiers: BTreeMap<u128, u32>,
+    loyalty: Option<LoyaltyConfig>,
+    positions: bool,
+    share_token: Option<ActorId>,
+    detached_shares: u128,
+    owed_refunds: BTreeMap<ActorId, u128>,
+    owed_shares: BTreeMap<ActorId, u128>,
+    vault_shares: BTreeMap<ActorId, u128>,
+    vault_deposited_at: BTreeMap<ActorId, u64>,
+    vault_total_shares: u128,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Reward {
+    tokens_per_stake: u128,
     distribution_time: u64,
     produced_time: u64,
     reward_total: u128,
     all_produced: u128,
     reward_produced: u128,
-    stakers: HashMap<ActorId, Staker>,
-
-    transactions: BTreeMap<ActorId, Transaction<StakingAction>>,
-    current_tid: TransactionId,
+    epochs: Vec<Epoch>,
+    curve: EmissionCurve,
+    apr: u32,
+    apr_coverage: u64,
+    apr_accrued: u128,
+    updated_at: u64,
 }
 
 static mut STAKING: Option<Staking> = None;
 const DECIMALS_FACTOR: u128 = 10_u128.pow(20);
+/// A year in milliseconds
+const YEAR: u64 = 365 * 24 * 60 * 60 * 1000;
+/// One in the Q63 fixed-point format
+const Q63: u128 = 1 << 63;
+/// 2^(-1/2^i) for i from 1 to 32 in the Q63 fixed-point format
+const EXP2_NEG_FRACTIONS: [u128; 32] = [
+    6_521_908_912_666_391_106,
+    7_755_900_482_342_532_474,
+    8_457_869_449_776_733_335,
+    8_832_331_321_595_618_838,
+    9_025_734_193_507_008_925,
+    9_124_017_994_966_720_698,
+    9_173_560_510_430_823_462,
+    9_198_432_556_164_277_331,
+    9_210_893_855_724_328_809,
+    9_217_130_834_664_616_070,
+    9_220_250_907_674_776_491,
+    9_221_811_340_221_203_999,
+    9_222_591_655_524_303_666,
+    9_222_981_837_935_769_002,
+    9_223_176_935_331_786_073,
+    9_223_274_485_577_403_901,
+    9_223_323_261_087_119_913,
+    9_223_347_648_938_705_290,
+    9_223_359_842_888_679_895,
+    9_223_365_939_869_712_687,
+    9_223_368_988_361_740_456,
+    9_223_370_512_608_132_184,
+    9_223_371_274_731_422_509,
+    9_223_371_655_793_091_287,
+    9_223_371_846_323_931_579,
+    9_223_371_941_589_353_202,
+    9_223_371_989_222_064_382,
+    9_223_372_013_038_420_064,
+    9_223_372_024_946_597_928,
+    9_223_372_030_900_686_866,
+    9_223_372_033_877_731_337,
+    9_223_372_035_366_253_572,
+];
+/// The weight of the first halving period in the fixed-point format
+const HALVING_WEIGHT: u128 = 1 << 32;
 
-impl Staking {
-    /// Transfers `amount` tokens from `sender` account to `recipient` account.
+impl Reward {
+    /// Calculates the reward produced by the given time, including the epochs
     /// Arguments:
-    /// * `from`: sender account
-    /// * `to`: recipient account
-    /// * `amount`: amount of tokens
-    async fn transfer_tokens(
-        &mut self,
-        token_address: &ActorId,
-        from: &ActorId,
-        to: &ActorId,
-        amount_tokens: u128,
-    ) -> Result<(), Error> {
-        let payload = LogicAction::Transfer {
-            sender: *from,
-            recipient: *to,
-            amount: amount_tokens,
-        };
-
-        let transaction_id = self.current_tid;
-        self.current_tid = self.current_tid.saturating_add(99);
-
-        let payload = FTokenAction::Message {
-            transaction_id,
-            payload,
-        };
-
-        let result = msg::send_for_reply_as(*token_address, payload, 0, 0)?.await?;
+    /// `time`: the timestamp up to which the reward is calculated
+    fn produced(&self, time: u64) -> u128 {
+        self.epochs
+            .iter()
+            .fold(self.window_produced(time), |produced, epoch| {
+                produced.saturating_add(epoch_produced(epoch, time))
+            })
+    }
 
-        if let FTokenEvent::Err = result {
-            Err(Error::TransferTokens)
-        } else {
-            Ok(())
+    /// Calculates the reward produced by the given time outside the pending epochs
+    /// Arguments:
+    /// `time`: the timestamp up to which the reward is calculated
+    fn window_produced(&self, time: u64) -> u128 {
+        if self.distribution_time == 0 {
+            return self.all_produced;
         }
-    }
 
-    /// Calculates the reward produced so far
-    fn produced(&mut self) -> u128 {
-        let mut elapsed_time = exec::block_timestamp() - self.produced_time;
+        let mut elapsed_time = time.saturating_sub(self.produced_time);
 
         if elapsed_time > self.distribution_time {
             elapsed_time = self.distribution_time;
         }
 
         self.all_produced
-            + self.reward_total.saturating_mul(elapsed_time as u128)
-                / self.distribution_time as u128
+            + curve_produced(
+                &self.curve,
+                self.reward_total,
+                elapsed_time,
+                self.distribution_time,
+            )
     }
 
-    /// Updates the reward produced so far and calculates tokens per stake
-    fn update_reward(&mut self) {
-        let reward_produced_at_now = self.produced();
+    /// Calculates the reward that the distribution window is yet to produce after the given time
+    /// The epochs aren't included
+    /// Arguments:
+    /// `time`: the timestamp from which the reward is calculated
+    fn unproduced(&self, time: u64) -> u128 {
+        if self.distribution_time == 0 {
+            return 0;
+        }
+
+        (self.all_produced + self.reward_total).saturating_sub(self.window_produced(time))
+    }
+
+    /// Updates the reward produced by the given time and calculates tokens per stake
+    /// Arguments:
+    /// `time`: the timestamp up to which the reward is produced
+    /// `total_staked`: the effective balance of all stakers of the pool
+    fn update(&mut self, time: u64, total_staked: u128) {
+        let reward_produced_at_now = self.produced(time);
 
         if reward_produced_at_now > self.reward_produced {
             let produced_new = reward_produced_at_now - self.reward_produced;
 
-            if self.total_staked > 0 {
+            if total_staked > 0 {
                 self.tokens_per_stake = self
                     .tokens_per_stake
-                    .saturating_add((produced_new * DECIMALS_FACTOR) / self.total_staked);
+                    .saturating_add((produced_new * DECIMALS_FACTOR) / total_staked);
             }
 
             self.reward_produced = self.reward_produced.saturating_add(produced_new);
         }
+
+        // The fixed-APR interest doesn't depend on the total stake
+        if self.apr > 0 && time > self.updated_at {
+            let elapsed_time = time - self.updated_at;
+
+            self.tokens_per_stake = self.tokens_per_stake.saturating_add(interest(
+                DECIMALS_FACTOR,
+                self.apr,
+                elapsed_time,
+            ));
+            self.apr_accrued =
+                self.apr_accrued
+                    .saturating_add(interest(total_staked, self.apr, elapsed_time));
+        }
+
+        self.updated_at = self.updated_at.max(time);
+
+        // The finished epochs are moved to the produced reward
+        let (finished, epochs): (Vec<_>, Vec<_>) = mem::take(&mut self.epochs)
+            .into_iter()
+            .partition(|epoch| epoch.end <= time);
+
+        for epoch in finished {
+            self.all_produced = self.all_produced.saturating_add(epoch.amount);
+        }
+
+        self.epochs = epochs;
+    }
+
+    /// Calculates the fixed-APR interest that the program owes
+    /// It's the interest accrued so far and the interest for the coverage period ahead
+    /// Arguments:
+    /// `time`: the current timestamp
+    /// `total_staked`: the effective balance of all stakers of the pool
+    fn apr_liability(&self, time: u64, total_staked: u128) -> u128 {
+        let pending = interest(total_staked, self.apr, time.saturating_sub(self.updated_at));
+
+        self.apr_accrued
+            .saturating_add(pending)
+            .saturating_add(interest(total_staked, self.apr, self.apr_coverage))
     }
 
     /// Calculates the maximum possible reward
@@ -98,132 +226,2790 @@ impl Staking {
     fn get_max_reward(&self, amount: u128) -> u128 {
         (amount * self.tokens_per_stake) / DECIMALS_FACTOR
     }
+}
+
+impl Pool {
+    /// Updates the reward produced so far and calculates tokens per stake
+    /// The boosts of locks that have expired since the last update are removed
+    /// at their unlock time, so they don't earn anything after it
+    fn update_reward(&mut self) {
+        let now = exec::block_timestamp();
+
+        while let Some(entry) = self.lock_expirations.first_entry() {
+            if *entry.key() > now {
+                break;
+            }
+
+            let (unlock_time, expired) = entry.remove_entry();
+            self.update_reward_at(unlock_time);
+
+            for (id, boost) in expired {
+                self.decrease_weight(&id, boost);
+            }
+        }
+
+        self.update_reward_at(now);
+    }
 
-    /// Calculates the reward of the staker that is currently available
-    /// The return value cannot be less than zero according to the algorithm
-    fn calc_reward(&mut self) -> Result<u128, Error> {
-        match self.stakers.get(&msg::source()) {
-            Some(staker) => Ok(self.get_max_reward(staker.balance) + staker.reward_allowed
-                - staker.reward_debt
-                - staker.distributed),
-            None => Err(Error::StakerNotFound),
+    /// Updates the rewards produced by the given time for every reward token
+    /// Arguments:
+    /// `time`: the timestamp up to which the rewards are produced
+    fn update_reward_at(&mut self, time: u64) {
+        let total_staked = self.total_staked;
+
+        for reward in self.rewards.values_mut() {
+            reward.update(time, total_staked);
         }
     }
 
-    /// Updates the staking contract.
-    /// Sets the reward to be distributed within distribution time
-    /// param 'config' - updated configuration
-    fn update_staking(&mut self, config: InitStaking) -> Result<StakingEvent, Error> {
-        if msg::source() != self.owner {
-            return Err(Error::NotOwner);
+    /// Adds to the staker's effective balance
+    /// The rewards produced before are written to the staker's debt
+    /// Arguments:
+    /// `id`: the staker
+    /// `amount`: the added effective amount, before the tier weight is applied
+    fn increase_weight(&mut self, id: &ActorId, amount: u128) {
+        let staker = self.stakers.entry(*id).or_default();
+        let weight = staker_weight(staker);
+
+        staker.effective_balance = staker.effective_balance.saturating_add(amount);
+
+        let new_weight = staker_weight(staker);
+
+        // The differences of the rounded rewards keep the staker's reward from going negative
+        for (token, reward) in &self.rewards {
+            let staker_reward = staker.rewards.entry(*token).or_default();
+            staker_reward.reward_debt = staker_reward
+                .reward_debt
+                .saturating_add(reward.get_max_reward(new_weight) - reward.get_max_reward(weight));
         }
 
-        if config.reward_total == 0 {
-            return Err(Error::ZeroReward);
+        staker.penalty_reward.reward_debt = staker.penalty_reward.reward_debt.saturating_add(
+            accumulated(new_weight, self.penalty_per_stake)
+                - accumulated(weight, self.penalty_per_stake),
+        );
+
+        self.total_staked = self.total_staked.saturating_add(new_weight - weight);
+    }
+
+    /// Subtracts from the staker's effective balance
+    /// The rewards produced before are kept as allowed to the staker
+    /// Arguments:
+    /// `id`: the staker
+    /// `amount`: the subtracted effective amount, before the tier weight is applied
+    fn decrease_weight(&mut self, id: &ActorId, amount: u128) {
+        let Some(staker) = self.stakers.get_mut(id) else {
+            return;
+        };
+        let weight = staker_weight(staker);
+
+        staker.effective_balance = staker.effective_balance.saturating_sub(amount);
+
+        let new_weight = staker_weight(staker);
+
+        for (token, reward) in &self.rewards {
+            let staker_reward = staker.rewards.entry(*token).or_default();
+            staker_reward.reward_allowed = staker_reward
+                .reward_allowed
+                .saturating_add(reward.get_max_reward(weight) - reward.get_max_reward(new_weight));
         }
 
-        if config.distribution_time == 0 {
-            return Err(Error::ZeroTime);
+        staker.penalty_reward.reward_allowed = staker.penalty_reward.reward_allowed.saturating_add(
+            accumulated(weight, self.penalty_per_stake)
+                - accumulated(new_weight, self.penalty_per_stake),
+        );
+
+        self.total_staked = self.total_staked.saturating_sub(weight - new_weight);
+    }
+
+    /// Calculates the loyalty multiplier that the staker has earned by now
+    /// Arguments:
+    /// `staker`: the staker
+    fn loyalty_weight(&self, staker: &Staker) -> u32 {
+        let Some(LoyaltyConfig {
+            step,
+            step_bonus,
+            max_bonus,
+        }) = &self.loyalty
+        else {
+            return 0;
+        };
+
+        if staker.balance == 0 {
+            return 0;
+        }
+
+        let steps = exec::block_timestamp().saturating_sub(staker.since) / step;
+        let bonus = u64::from(*step_bonus)
+            .saturating_mul(steps)
+            .min((*max_bonus).into()) as u32;
+
+        BASIS_POINTS.saturating_add(bonus)
+    }
+
+    /// Moves the staker to the tier matching the balance and applies
+    /// the loyalty multiplier earned so far
+    /// The rewards must be updated before, so they're settled at the old weight
+    /// Arguments:
+    /// `id`: the staker
+    fn checkpoint(&mut self, id: &ActorId) {
+        let Some(staker) = self.stakers.get(id) else {
+            return;
+        };
+
+        // The vault's balance pools the deposits of its holders,
+        // so it earns no tier or loyalty bonus on its own
+        let (tier_weight, loyalty_weight) = if *id == vault_key() {
+            (0, 0)
+        } else {
+            let tier_weight = self
+                .tiers
+                .range(..=staker.balance)
+                .next_back()
+                .map_or(0, |(_min_balance, weight)| *weight);
+
+            (tier_weight, self.loyalty_weight(staker))
+        };
+
+        if staker.tier_weight == tier_weight && staker.loyalty_weight == loyalty_weight {
+            return;
+        }
+
+        let Some(staker) = self.stakers.get_mut(id) else {
+            return;
+        };
+        let weight = staker_weight(staker);
+        staker.tier_weight = tier_weight;
+        staker.loyalty_weight = loyalty_weight;
+        let new_weight = staker_weight(staker);
+
+        for (token, reward) in &self.rewards {
+            let staker_reward = staker.rewards.entry(*token).or_default();
+            let (produced, new_produced) = (
+                reward.get_max_reward(weight),
+                reward.get_max_reward(new_weight),
+            );
+
+            if new_produced > produced {
+                staker_reward.reward_debt = staker_reward
+                    .reward_debt
+                    .saturating_add(new_produced - produced);
+            } else {
+                staker_reward.reward_allowed = staker_reward
+                    .reward_allowed
+                    .saturating_add(produced - new_produced);
+            }
+        }
+
+        let penalty_reward = &mut staker.penalty_reward;
+        let (produced, new_produced) = (
+            accumulated(weight, self.penalty_per_stake),
+            accumulated(new_weight, self.penalty_per_stake),
+        );
+
+        if new_produced > produced {
+            penalty_reward.reward_debt = penalty_reward
+                .reward_debt
+                .saturating_add(new_produced - produced);
+        } else {
+            penalty_reward.reward_allowed = penalty_reward
+                .reward_allowed
+                .saturating_add(produced - new_produced);
         }
 
+        self.total_staked = self
+            .total_staked
+            .saturating_sub(weight)
+            .saturating_add(new_weight);
+    }
+
+    /// Calculates the reward of the staker that is currently available
+    /// The return value cannot be less than zero according to the algorithm
+    /// Arguments:
+    /// `id`: the staker
+    /// `token`: the reward token
+    fn calc_reward(&self, id: &ActorId, token: &ActorId) -> Result<u128, Error> {
+        let staker = self.stakers.get(id).ok_or(Error::StakerNotFound)?;
+        let reward = self.rewards.get(token).ok_or(Error::RewardTokenNotFound)?;
+        let staker_reward = staker.rewards.get(token).cloned().unwrap_or_default();
+
+        Ok(
+            reward.get_max_reward(staker_weight(staker)) + staker_reward.reward_allowed
+                - staker_reward.reward_debt
+                - staker_reward.distributed,
+        )
+    }
+
+    /// Calculates the staker's share of the penalties that is currently available
+    /// Arguments:
+    /// `id`: the staker
+    fn calc_penalty_reward(&self, id: &ActorId) -> Result<u128, Error> {
+        let staker = self.stakers.get(id).ok_or(Error::StakerNotFound)?;
+        let penalty_reward = &staker.penalty_reward;
+
+        Ok(accumulated(staker_weight(staker), self.penalty_per_stake)
+            + penalty_reward.reward_allowed
+            - penalty_reward.reward_debt
+            - penalty_reward.distributed)
+    }
+
+    /// Calculates the reward that the current schedule of the token is yet to produce
+    /// Arguments:
+    /// `token`: the reward token
+    fn unproduced(&self, token: &ActorId) -> u128 {
+        self.rewards
+            .get(token)
+            .map_or(0, |reward| reward.unproduced(exec::block_timestamp()))
+    }
+
+    /// Sets the reward to be distributed within distribution time
+    /// The reward token is added to the pool if it isn't there yet
+    /// param 'config' - updated configuration, checked by `check_config`
+    fn update(&mut self, config: InitStaking) {
+        self.update_reward();
         self.staking_token_address = config.staking_token_address;
-        self.reward_token_address = config.reward_token_address;
-        self.distribution_time = config.distribution_time;
+
+        let reward = self.rewards.entry(config.reward_token_address).or_default();
+
+        reward.distribution_time = config.distribution_time;
+        reward.all_produced = reward.window_produced(exec::block_timestamp());
+        reward.produced_time = exec::block_timestamp();
+        reward.reward_total = config.reward_total;
+        reward.curve = config.curve;
+    }
+
+    /// Adds the reward to the current schedule of the token
+    /// The reward left unproduced is kept and distributed along with the added one
+    /// within the schedule's distribution time since now
+    /// Arguments:
+    /// `token`: the reward token
+    /// `amount`: the added reward
+    fn notify_reward_amount(&mut self, token: &ActorId, amount: u128) {
+        let leftover = self.unproduced(token);
 
         self.update_reward();
-        self.all_produced = self.reward_produced;
-        self.produced_time = exec::block_timestamp();
-        self.reward_total = config.reward_total;
 
-        Ok(StakingEvent::Updated)
+        if let Some(reward) = self.rewards.get_mut(token) {
+            reward.all_produced = reward.window_produced(exec::block_timestamp());
+            reward.produced_time = exec::block_timestamp();
+            reward.reward_total = leftover.saturating_add(amount);
+        }
     }
 
-    /// Stakes the tokens
+    /// Sets the fixed annual rate of the reward token, which is paid on top of the pool share
     /// Arguments:
-    /// `amount`: the number of tokens for the stake
-    async fn stake(&mut self, amount: u128) -> Result<StakingEvent, Error> {
+    /// `token`: the reward token
+    /// `rate`: the annual rate in basis points
+    /// `coverage`: the period ahead for which the funds must cover the interest
+    fn set_fixed_apr(&mut self, token: &ActorId, rate: u32, coverage: u64) {
+        self.update_reward();
+
+        let reward = self.rewards.entry(*token).or_default();
+
+        reward.apr = rate;
+        reward.apr_coverage = coverage;
+        reward.updated_at = exec::block_timestamp();
+    }
+
+    /// Credits the staked tokens to the staker
+    /// Arguments:
+    /// `id`: the staker credited with the tokens
+    /// `amount`: the number of staked tokens
+    /// `multiplier`: the reward multiplier of the stake in basis points
+    /// `term`: the lock term, if the tokens are locked
+    fn deposit(
+        &mut self,
+        id: &ActorId,
+        amount: u128,
+        multiplier: u32,
+        term: Option<u64>,
+    ) -> StakingEvent {
+        self.update_reward();
+        let effective_amount = amount.saturating_mul(multiplier.into()) / BASIS_POINTS as u128;
+
+        self.increase_weight(id, effective_amount);
+
+        let staker = self.stakers.entry(*id).or_default();
+        add_balance(staker, amount);
+        self.checkpoint(id);
+
+        let Some(term) = term else {
+            return StakingEvent::StakeAccepted(amount);
+        };
+
+        let unlock_time = exec::block_timestamp().saturating_add(term);
+        let staker = self.stakers.entry(*id).or_default();
+        staker.locks.push(Lock {
+            amount,
+            unlock_time,
+        });
+
+        let boost = effective_amount - amount;
+
+        if boost > 0 {
+            self.lock_expirations
+                .entry(unlock_time)
+                .or_default()
+                .push((*id, boost));
+        }
+
+        StakingEvent::StakeLocked {
+            amount,
+            unlock_time,
+        }
+    }
+
+    /// Checks that the staker can take the amount out of the staking
+    /// Arguments:
+    /// `id`: the staker
+    /// `amount`: the number of tokens
+    fn check_unlocked(&mut self, id: &ActorId, amount: u128) -> Result<(), Error> {
+        let staker = self.stakers.get_mut(id).ok_or(Error::StakerNotFound)?;
+
+        if staker.balance < amount {
+            return Err(Error::InsufficentBalance);
+        }
+
+        let now = exec::block_timestamp();
+        staker.locks.retain(|lock| lock.unlock_time > now);
+        let locked: u128 = staker.locks.iter().map(|lock| lock.amount).sum();
+
+        if staker.balance - locked < amount {
+            return Err(Error::LockedTokens);
+        }
+
+        Ok(())
+    }
+
+    /// Moves the staked tokens to the unbonding queue
+    /// The tokens stop earning rewards at once
+    /// Arguments:
+    /// `id`: the staker
+    /// `amount`: the number of unstaked tokens
+    fn request_unstake(&mut self, id: &ActorId, amount: u128) -> Result<StakingEvent, Error> {
         if amount == 0 {
             return Err(Error::ZeroAmount);
         }
 
-        let token_address = self.staking_token_address;
+        self.check_unlocked(id, amount)?;
+        let penalty = self.calc_penalty(id, amount);
 
-        self.transfer_tokens(&token_address, &msg::source(), &exec::program_id(), amount)
-            .await?;
+        self.update_reward();
+        self.decrease_weight(id, amount);
+
+        let maturity = exec::block_timestamp().saturating_add(self.unbonding_period);
+        let staker = self.stakers.get_mut(id).ok_or(Error::StakerNotFound)?;
+
+        subtract_balance(staker, amount);
+        staker.unbonding.push(UnbondingChunk {
+            amount: amount - penalty,
+            maturity,
+        });
+        self.checkpoint(id);
+        self.charge_penalty(penalty);
+
+        Ok(StakingEvent::Unbonding {
+            amount,
+            penalty,
+            maturity,
+        })
+    }
+
+    /// Calculates the penalty for taking the tokens out before the holding period is over
+    /// The penalty rate decreases linearly from the configured one to zero
+    /// over the holding period since the deposit
+    /// Arguments:
+    /// `id`: the staker
+    /// `amount`: the number of tokens taken out
+    fn calc_penalty(&self, id: &ActorId, amount: u128) -> u128 {
+        let Some(staker) = self.stakers.get(id) else {
+            return 0;
+        };
+
+        self.calc_penalty_at(staker.staked_at, staker_weight(staker), amount)
+    }
+
+    /// Calculates the penalty for taking out the tokens deposited at the given time
+    /// Arguments:
+    /// `staked_at`: the deposit time of the tokens
+    /// `weight`: the weight of the staker holding them
+    /// `amount`: the number of tokens taken out
+    fn calc_penalty_at(&self, staked_at: u64, weight: u128, amount: u128) -> u128 {
+        let Some(PenaltyConfig {
+            rate,
+            holding_period,
+            destination,
+        }) = &self.penalty
+        else {
+            return 0;
+        };
+
+        let held = exec::block_timestamp().saturating_sub(staked_at);
+
+        if held >= *holding_period {
+            return 0;
+        }
+
+        // Nobody else is there to receive the penalty
+        if *destination == PenaltyDestination::Stakers && self.total_staked <= weight {
+            return 0;
+        }
+
+        let rate =
+            u128::from(*rate) * u128::from(holding_period - held) / u128::from(*holding_period);
+
+        amount.saturating_mul(rate) / u128::from(BASIS_POINTS)
+    }
+
+    /// Keeps the penalty for the treasury or distributes it to the remaining stakers
+    /// The penalty is distributed in the staking token apart from the funded rewards
+    /// Arguments:
+    /// `penalty`: the number of tokens
+    fn charge_penalty(&mut self, penalty: u128) {
+        if penalty == 0 {
+            return;
+        }
+
+        match self.penalty.as_ref().map(|config| &config.destination) {
+            Some(PenaltyDestination::Stakers) if self.total_staked > 0 => {
+                self.penalty_per_stake = self
+                    .penalty_per_stake
+                    .saturating_add(penalty * DECIMALS_FACTOR / self.total_staked);
+            }
+            _ => self.unpaid_penalties = self.unpaid_penalties.saturating_add(penalty),
+        }
+    }
+
+    /// Moves the staked tokens to another staker without token transfers
+    /// The rewards accrued so far stay with the sender,
+    /// and the tokens keep their deposit and continuous staking times
+    /// Arguments:
+    /// `from`: the sender
+    /// `to`: the recipient
+    /// `amount`: the number of tokens
+    fn transfer_stake(
+        &mut self,
+        from: &ActorId,
+        to: &ActorId,
+        amount: u128,
+    ) -> Result<StakingEvent, Error> {
+        self.check_unlocked(from, amount)?;
 
         self.update_reward();
-        let amount_per_token = self.get_max_reward(amount);
+        self.decrease_weight(from, amount);
 
-        self.stakers
-            .entry(msg::source())
-            .and_modify(|stake| {
-                stake.reward_debt = stake.reward_debt.saturating_add(amount_per_token);
-                stake.balance = stake.balance.saturating_add(amount);
-            })
-            .or_insert(Staker {
-                reward_debt: amount_per_token,
-                balance: amount,
-                ..Default::default()
-            });
-        self.total_staked = self.total_staked.saturating_add(amount);
-        Ok(StakingEvent::StakeAccepted(amount))
+        let sender = self.stakers.get_mut(from).ok_or(Error::StakerNotFound)?;
+        let (staked_at, since) = (sender.staked_at, sender.since);
+
+        subtract_balance(sender, amount);
+        self.checkpoint(from);
+
+        self.increase_weight(to, amount);
+
+        let recipient = self.stakers.entry(*to).or_default();
+        add_balance_at(recipient, amount, staked_at, since);
+        self.checkpoint(to);
+
+        Ok(StakingEvent::StakeTransferred { to: *to, amount })
+    }
+
+    /// Moves the unbonding chunk back to the staked tokens
+    /// Arguments:
+    /// `id`: the staker
+    /// `index`: the index of the chunk in the staker's unbonding queue
+    fn rebond(&mut self, id: &ActorId, index: u32) -> Result<StakingEvent, Error> {
+        self.update_reward();
+
+        let staker = self.stakers.get_mut(id).ok_or(Error::StakerNotFound)?;
+        let index = index as usize;
+
+        if index >= staker.unbonding.len() {
+            return Err(Error::UnbondingNotFound);
+        }
+
+        let UnbondingChunk { amount, .. } = staker.unbonding.remove(index);
+
+        add_balance(staker, amount);
+        self.increase_weight(id, amount);
+        self.checkpoint(id);
+
+        Ok(StakingEvent::Rebonded(amount))
+    }
+
+    /// Whether the pool holds none of the staking tokens: no stakers, no detached shares
+    /// and no penalties or refunds waiting to be paid
+    fn holds_no_stake(&self) -> bool {
+        self.stakers.is_empty()
+            && self.detached_shares == 0
+            && self.owed_refunds.is_empty()
+            && self.unpaid_penalties == 0
+            && self.forfeited_penalties == 0
+    }
+
+    /// Takes the staker's matured unbonding chunks out of the queue
+    /// Arguments:
+    /// `id`: the staker
+    fn take_matured(&mut self, id: &ActorId) -> Result<Vec<UnbondingChunk>, Error> {
+        let staker = self.stakers.get_mut(id).ok_or(Error::StakerNotFound)?;
+        let now = exec::block_timestamp();
+        let (matured, unbonding) = mem::take(&mut staker.unbonding)
+            .into_iter()
+            .partition(|chunk| chunk.maturity <= now);
+
+        staker.unbonding = unbonding;
+
+        Ok(matured)
+    }
+
+    /// Puts the unbonding chunks taken out of the queue back in front of it
+    /// Arguments:
+    /// `id`: the staker
+    /// `chunks`: the chunks
+    fn restore_unbonding(&mut self, id: &ActorId, mut chunks: Vec<UnbondingChunk>) {
+        if let Some(staker) = self.stakers.get_mut(id) {
+            chunks.append(&mut staker.unbonding);
+            staker.unbonding = chunks;
+        }
     }
 
-    ///Sends reward to the staker
-    async fn send_reward(&mut self) -> Result<StakingEvent, Error> {
-        self.update_reward();
-        let reward = self.calc_reward()?;
+    /// Removes the staker left with nothing: no stake, no unbonding tokens and no rewards
+    /// Returns whether the staker is gone from the pool
+    /// Arguments:
+    /// `id`: the staker
+    fn remove_if_empty(&mut self, id: &ActorId) -> bool {
+        let Some(staker) = self.stakers.get(id) else {
+            return true;
+        };
+
+        let is_empty = staker.balance == 0
+            && staker.effective_balance == 0
+            && staker.unbonding.is_empty()
+            && self
+                .rewards
+                .keys()
+                .all(|token| self.calc_reward(id, token).unwrap_or_default() == 0)
+            && self.calc_penalty_reward(id).unwrap_or_default() == 0;
+
+        if is_empty {
+            self.stakers.remove(id);
+        }
+
+        is_empty
+    }
+
+    /// Takes the staker's unlocked tokens out of the pool, forfeiting the rewards
+    /// The matured unbonding chunks must be taken out before
+    /// The early withdrawal penalty is charged on the taken tokens, and in the pools with
+    /// an unbonding period they start unbonding instead of leaving the pool at once
+    /// The staker is removed if nothing is left
+    /// Arguments:
+    /// `id`: the staker
+    /// `amount`: the number of unlocked tokens taken out of the staked balance
+    /// `penalty`: the penalty charged on them
+    /// `owner`: the owner of the program
+    fn emergency_exit(&mut self, id: &ActorId, amount: u128, penalty: u128, owner: &ActorId) {
+        self.update_reward();
+        self.forfeit_rewards(id, owner);
+        self.decrease_weight(id, amount);
+
+        let maturity = exec::block_timestamp().saturating_add(self.unbonding_period);
+        let unbonds = self.unbonding_period > 0;
+
+        let Some(staker) = self.stakers.get_mut(id) else {
+            return;
+        };
+
+        subtract_balance(staker, amount);
+
+        if unbonds && amount > 0 {
+            staker.unbonding.push(UnbondingChunk {
+                amount: amount - penalty,
+                maturity,
+            });
+        }
+
+        self.checkpoint(id);
+        self.charge_penalty(penalty);
+        self.remove_if_empty(id);
+    }
+
+    /// Forfeits the staker's rewards, the stake stays in place
+    /// The rewards are redistributed to the other stakers or kept for the owner,
+    /// and they're redistributed if the ownership is renounced
+    /// The rewards must be updated before
+    /// Arguments:
+    /// `id`: the staker
+    /// `owner`: the owner of the program
+    fn forfeit_rewards(&mut self, id: &ActorId, owner: &ActorId) {
+        let forfeited: Vec<_> = self
+            .rewards
+            .keys()
+            .map(|token| (*token, self.calc_reward(id, token).unwrap_or_default()))
+            .filter(|(_, amount)| *amount > 0)
+            .collect();
+        let forfeited_penalties = self.calc_penalty_reward(id).unwrap_or_default();
+
+        let Some(staker) = self.stakers.get_mut(id) else {
+            return;
+        };
+        let weight = staker_weight(staker);
+
+        for (token, amount) in &forfeited {
+            let staker_reward = staker.rewards.entry(*token).or_default();
+            staker_reward.distributed = staker_reward.distributed.saturating_add(*amount);
+        }
+
+        staker.penalty_reward.distributed = staker
+            .penalty_reward
+            .distributed
+            .saturating_add(forfeited_penalties);
+
+        let others = self.total_staked.saturating_sub(weight);
+        let redistributed = others > 0
+            && (self.forfeit_destination == ForfeitDestination::Stakers || owner.is_zero());
+
+        if !redistributed {
+            for (token, amount) in forfeited {
+                let kept = self.forfeited.entry(token).or_default();
+                *kept = kept.saturating_add(amount);
+            }
+
+            self.forfeited_penalties = self.forfeited_penalties.saturating_add(forfeited_penalties);
+
+            return;
+        }
+
+        // The staker's own share of the redistributed rewards is written to the debt
+        for (token, amount) in forfeited {
+            if let Some(reward) = self.rewards.get_mut(&token) {
+                let produced = reward.get_max_reward(weight);
+                reward.tokens_per_stake = reward
+                    .tokens_per_stake
+                    .saturating_add(amount * DECIMALS_FACTOR / others);
+
+                let staker_reward = staker.rewards.entry(token).or_default();
+                staker_reward.reward_debt = staker_reward
+                    .reward_debt
+                    .saturating_add(reward.get_max_reward(weight) - produced);
+            }
+        }
+
+        let produced = accumulated(weight, self.penalty_per_stake);
+        self.penalty_per_stake = self
+            .penalty_per_stake
+            .saturating_add(forfeited_penalties * DECIMALS_FACTOR / others);
+        staker.penalty_reward.reward_debt = staker
+            .penalty_reward
+            .reward_debt
+            .saturating_add(accumulated(weight, self.penalty_per_stake) - produced);
+    }
+
+    /// Records the staking tokens that failed to return to the depositor
+    /// Arguments:
+    /// `account`: the depositor
+    /// `amount`: the number of tokens
+    fn owe_refund(&mut self, account: &ActorId, amount: u128) {
+        let owed = self.owed_refunds.entry(*account).or_default();
+        *owed = owed.saturating_add(amount);
+    }
+
+    /// Records the shares that failed to be minted back to the staker
+    /// Arguments:
+    /// `account`: the staker
+    /// `shares`: the number of shares
+    fn owe_shares(&mut self, account: &ActorId, shares: u128) {
+        let owed = self.owed_shares.entry(*account).or_default();
+        *owed = owed.saturating_add(shares);
+    }
+
+    /// Mints the vault's shares to the holder, averaging the deposit time
+    /// of the holder's shares with the minted ones
+    /// Arguments:
+    /// `holder`: the holder of the shares
+    /// `shares`: the number of shares
+    /// `deposited_at`: the deposit time of the minted shares
+    fn mint_vault_shares(&mut self, holder: &ActorId, shares: u128, deposited_at: u64) {
+        let owned = self.vault_shares.entry(*holder).or_default();
+        let total = owned.saturating_add(shares);
+        let time = self
+            .vault_deposited_at
+            .entry(*holder)
+            .or_insert(deposited_at);
+
+        if total > 0 {
+            let sum = u128::from(*time).saturating_mul(*owned)
+                + u128::from(deposited_at).saturating_mul(shares);
+
+            *time = (sum / total) as u64;
+        }
+
+        *owned = total;
+        self.vault_total_shares = self.vault_total_shares.saturating_add(shares);
+    }
+
+    /// Burns the holder's vault shares
+    /// Arguments:
+    /// `holder`: the holder of the shares
+    /// `shares`: the number of shares
+    fn burn_vault_shares(&mut self, holder: &ActorId, shares: u128) {
+        if let Some(owned) = self.vault_shares.get_mut(holder) {
+            *owned = owned.saturating_sub(shares);
+
+            if *owned == 0 {
+                self.vault_shares.remove(holder);
+                self.vault_deposited_at.remove(holder);
+            }
+        }
+
+        self.vault_total_shares = self.vault_total_shares.saturating_sub(shares);
+    }
+
+    /// Calculates the vault's assets, the reward harvested so far included
+    fn vault_assets(&self) -> u128 {
+        self.stakers
+            .get(&vault_key())
+            .map_or(0, |vault| vault.balance)
+    }
+
+    /// Converts the deposited tokens to the vault's shares, rounding down
+    /// Arguments:
+    /// `amount`: the number of tokens
+    fn vault_shares_for(&self, amount: u128) -> u128 {
+        let assets = self.vault_assets();
+
+        if self.vault_total_shares == 0 || assets == 0 {
+            return amount;
+        }
+
+        mul_div(amount, self.vault_total_shares, assets)
+    }
+
+    /// Converts the vault's shares to the withdrawn tokens, rounding down
+    /// Arguments:
+    /// `shares`: the number of shares
+    fn vault_assets_for(&self, shares: u128) -> u128 {
+        if self.vault_total_shares == 0 {
+            return 0;
+        }
+
+        mul_div(shares, self.vault_assets(), self.vault_total_shares)
+    }
+
+    /// Calculates the vault's assets of one share scaled by `PRICE_SCALE`,
+    /// counting the reward that the vault hasn't harvested yet
+    fn price_per_share(&self) -> u128 {
+        if self.vault_total_shares == 0 {
+            return PRICE_SCALE;
+        }
+
+        let mut pool = self.clone();
+        pool.update_reward();
+
+        let pending = pool
+            .calc_reward(&vault_key(), &pool.staking_token_address)
+            .unwrap_or_default()
+            .saturating_add(pool.calc_penalty_reward(&vault_key()).unwrap_or_default());
+
+        mul_div(
+            pool.vault_assets().saturating_add(pending),
+            PRICE_SCALE,
+            pool.vault_total_shares,
+        )
+    }
+
+    /// Calculates the staker's reward in the staking token, the share of the penalties included
+    /// Arguments:
+    /// `id`: the staker
+    fn calc_restakable(&self, id: &ActorId) -> Result<u128, Error> {
+        let reward = self.calc_reward(id, &self.staking_token_address)?;
+
+        Ok(reward.saturating_add(self.calc_penalty_reward(id)?))
+    }
+
+    /// Restakes the staker's reward if the pool distributes the staking token
+    /// The reward is added to the balance without a token transfer,
+    /// the share of the penalties is restaked first
+    /// The restaked reward takes the deposit and continuous staking times of the balance,
+    /// so compounding keeps the loyalty multiplier and the early withdrawal clock
+    /// Returns the restaked amount and its part taken from the funded reward
+    /// Arguments:
+    /// `id`: the staker
+    /// `limit`: the maximum restaked amount, the whole reward if `None`
+    fn compound(&mut self, id: &ActorId, limit: Option<u128>) -> Result<(u128, u128), Error> {
+        let token = self.staking_token_address;
+
+        if !self.rewards.contains_key(&token) {
+            return Err(Error::RewardTokenNotFound);
+        }
+
+        self.update_reward();
+        let restakable = self.calc_restakable(id)?;
+        let penalty_reward = self.calc_penalty_reward(id)?;
+        let amount = limit.map_or(restakable, |limit| limit.min(restakable));
+
+        if amount == 0 {
+            return Err(Error::ZeroReward);
+        }
+
+        let from_penalties = amount.min(penalty_reward);
+        let from_reward = amount - from_penalties;
+
+        let staker = self.stakers.get_mut(id).ok_or(Error::StakerNotFound)?;
+        let staker_reward = staker.rewards.entry(token).or_default();
+
+        staker_reward.distributed = staker_reward.distributed.saturating_add(from_reward);
+        staker.penalty_reward.distributed = staker
+            .penalty_reward
+            .distributed
+            .saturating_add(from_penalties);
+
+        if staker.balance > 0 {
+            let (staked_at, since) = (staker.staked_at, staker.since);
+            add_balance_at(staker, amount, staked_at, since);
+        } else {
+            add_balance(staker, amount);
+        }
+
+        self.increase_weight(id, amount);
+        self.checkpoint(id);
+
+        Ok((amount, from_reward))
+    }
+}
+
+impl Staking {
+    /// Transfers `amount` tokens from `sender` account to `recipient` account.
+    /// Arguments:
+    /// * `from`: sender account
+    /// * `to`: recipient account
+    /// * `amount`: amount of tokens
+    async fn transfer_tokens(
+        &mut self,
+        token_address: &ActorId,
+        from: &ActorId,
+        to: &ActorId,
+        amount_tokens: u128,
+    ) -> Result<(), Error> {
+        let kind = self.token_kind(token_address);
+
+        if kind != TokenKind::Native && *from != exec::program_id() && msg::value() > 0 {
+            return Err(Error::InvalidValue);
+        }
+
+        let transaction_id = self.next_transaction_id();
+
+        token::adapter(kind, *token_address)
+            .transfer(transaction_id, *from, *to, amount_tokens)
+            .await
+    }
+
+    /// Returns the id of the next message to a token program
+    fn next_transaction_id(&mut self) -> TransactionId {
+        let transaction_id = self.current_tid;
+        self.current_tid = self.current_tid.saturating_add(99);
+
+        transaction_id
+    }
+
+    /// Returns how the token is moved
+    /// The zero address always stands for the native value
+    /// Arguments:
+    /// `token`: the token address
+    fn token_kind(&self, token: &ActorId) -> TokenKind {
+        if token.is_zero() {
+            return TokenKind::Native;
+        }
+
+        self.token_kinds.get(token).copied().unwrap_or_default()
+    }
+
+    /// Checks that the pool's tokens keep the kinds they are known by
+    /// Arguments:
+    /// `config`: the pool configuration
+    fn check_token_kinds(&self, config: &InitStaking) -> Result<(), Error> {
+        let tokens = [
+            (config.staking_token_address, config.staking_token_kind),
+            (config.reward_token_address, config.reward_token_kind),
+        ];
+
+        for (token, kind) in tokens {
+            self.check_token_kind(&token, kind)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the token keeps the kind it's known by,
+    /// and that only the zero address stands for the native value
+    /// Arguments:
+    /// `token`: the token address
+    /// `kind`: the kind of the token
+    fn check_token_kind(&self, token: &ActorId, kind: TokenKind) -> Result<(), Error> {
+        if token.is_zero() != (kind == TokenKind::Native)
+            || matches!(self.token_kinds.get(token), Some(known) if *known != kind)
+        {
+            return Err(Error::InvalidTokenKind);
+        }
+
+        Ok(())
+    }
+
+    /// Records the kinds of the pool's tokens
+    /// Arguments:
+    /// `config`: the pool configuration, checked by `check_token_kinds`
+    fn register_token_kinds(&mut self, config: &InitStaking) {
+        self.token_kinds
+            .insert(config.staking_token_address, config.staking_token_kind);
+        self.token_kinds
+            .insert(config.reward_token_address, config.reward_token_kind);
+    }
+
+    /// Mints the pool's share tokens to the staker if the pool has them
+    /// Arguments:
+    /// `to`: the staker
+    /// `amount`: the number of shares
+    async fn mint_shares(
+        &mut self,
+        pool_id: PoolId,
+        to: &ActorId,
+        amount: u128,
+    ) -> Result<(), Error> {
+        let Some(token) = self.pool(pool_id)?.share_token else {
+            return Ok(());
+        };
+
+        let payload = LogicAction::Mint {
+            recipient: *to,
+            amount,
+        };
+
+        self.send_token_message(&token, payload).await
+    }
+
+    /// Burns the staker's shares of the pool if the pool has them
+    /// The staker has to approve the program to spend the shares
+    /// Arguments:
+    /// `from`: the staker
+    /// `amount`: the number of shares
+    async fn burn_shares(
+        &mut self,
+        pool_id: PoolId,
+        from: &ActorId,
+        amount: u128,
+    ) -> Result<(), Error> {
+        let Some(token) = self.pool(pool_id)?.share_token else {
+            return Ok(());
+        };
+
+        let payload = LogicAction::Burn {
+            sender: *from,
+            amount,
+        };
+
+        self.send_token_message(&token, payload).await
+    }
+
+    /// Sends the message to the fungible token program
+    /// Arguments:
+    /// `token_address`: the token program
+    /// `payload`: the message
+    async fn send_token_message(
+        &mut self,
+        token_address: &ActorId,
+        payload: LogicAction,
+    ) -> Result<(), Error> {
+        let transaction_id = self.next_transaction_id();
+
+        ShardedToken(*token_address)
+            .send(transaction_id, payload)
+            .await
+    }
+
+    /// Checks that the message source is the owner or has the role
+    /// Arguments:
+    /// `role`: the role required for the operation
+    fn check_role(&self, role: Role) -> Result<(), Error> {
+        let source = msg::source();
+
+        if source == self.owner
+            || self
+                .roles
+                .get(&source)
+                .map_or(false, |roles| roles.contains(&role))
+        {
+            return Ok(());
+        }
+
+        Err(Error::NotOwner)
+    }
+
+    fn pool(&self, pool_id: PoolId) -> Result<&Pool, Error> {
+        self.pools.get(&pool_id).ok_or(Error::PoolNotFound)
+    }
+
+    fn pool_mut(&mut self, pool_id: PoolId) -> Result<&mut Pool, Error> {
+        self.pools.get_mut(&pool_id).ok_or(Error::PoolNotFound)
+    }
+
+    /// Creates a new staking pool
+    /// param 'config' - configuration of the pool
+    fn create_pool(&mut self, config: InitStaking) -> Result<StakingEvent, Error> {
+        self.check_role(Role::Admin)?;
+        check_config(&config)?;
+        self.check_token_kinds(&config)?;
+        self.check_funds(&config.reward_token_address, 0, config.reward_total)?;
+
+        Ok(self.add_pool(config))
+    }
+
+    /// Adds the pool without checking the funds
+    /// The initial pool can only be funded after the program is deployed
+    /// Arguments:
+    /// `config`: the pool configuration, checked by `check_config`
+    fn add_pool(&mut self, config: InitStaking) -> StakingEvent {
+        self.allocate(&config.reward_token_address, 0, config.reward_total);
+        self.register_token_kinds(&config);
+
+        let mut pool = Pool::default();
+        pool.update(config);
+
+        let pool_id = self.next_pool_id;
+        self.next_pool_id = self.next_pool_id.saturating_add(1);
+        self.pools.insert(pool_id, pool);
+
+        StakingEvent::PoolCreated(pool_id)
+    }
+
+    /// Checks that the funded reward tokens cover the new reward schedule
+    /// along with the reserve for the fixed-APR liabilities
+    /// Arguments:
+    /// `token`: the reward token
+    /// `released`: the reward left unproduced by the replaced schedule
+    /// `amount`: the reward of the new schedule
+    fn check_funds(&self, token: &ActorId, released: u128, amount: u128) -> Result<(), Error> {
+        let funds = self.funds.get(token).copied().unwrap_or_default();
+
+        if funds
+            .allocated
+            .saturating_sub(released)
+            .saturating_add(amount)
+            .saturating_add(self.liability(token))
+            > funds.funded
+        {
+            return Err(Error::InsufficientFunds);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the unallocated funds cover the fixed-APR liabilities
+    /// Arguments:
+    /// `token`: the reward token
+    /// `extra`: the liability to be added
+    fn check_reserve(&self, token: &ActorId, extra: u128) -> Result<(), Error> {
+        let funds = self.funds.get(token).copied().unwrap_or_default();
+
+        if self.liability(token).saturating_add(extra)
+            > funds.funded.saturating_sub(funds.allocated)
+        {
+            return Err(Error::InsufficientFunds);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the unallocated funds cover the fixed-APR interest on the new stake
+    /// Arguments:
+    /// `effective_amount`: the effective amount of the stake
+    fn check_stake_reserve(&self, pool_id: PoolId, effective_amount: u128) -> Result<(), Error> {
+        for (token, reward) in &self.pool(pool_id)?.rewards {
+            if reward.apr > 0 {
+                self.check_reserve(
+                    token,
+                    interest(effective_amount, reward.apr, reward.apr_coverage),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Calculates the fixed-APR liabilities of all pools in the reward token
+    /// Arguments:
+    /// `token`: the reward token
+    fn liability(&self, token: &ActorId) -> u128 {
+        let now = exec::block_timestamp();
+
+        self.pools
+            .values()
+            .filter_map(|pool| {
+                pool.rewards
+                    .get(token)
+                    .map(|reward| reward.apr_liability(now, pool.total_staked))
+            })
+            .fold(0, u128::saturating_add)
+    }
+
+    /// Moves the allocated reward tokens from the replaced reward schedule to the new one
+    /// Arguments:
+    /// `token`: the reward token
+    /// `released`: the reward left unproduced by the replaced schedule
+    /// `amount`: the reward of the new schedule
+    fn allocate(&mut self, token: &ActorId, released: u128, amount: u128) {
+        let funds = self.funds.entry(*token).or_default();
+        funds.allocated = funds
+            .allocated
+            .saturating_sub(released)
+            .saturating_add(amount);
+    }
+
+    /// Tops up the reward of the pool without discarding the unproduced one
+    /// Arguments:
+    /// `token`: the reward token
+    /// `amount`: the added reward
+    fn notify_reward_amount(
+        &mut self,
+        pool_id: PoolId,
+        token: ActorId,
+        amount: u128,
+    ) -> Result<StakingEvent, Error> {
+        self.check_role(Role::RewardManager)?;
+
+        if amount == 0 {
+            return Err(Error::ZeroReward);
+        }
+
+        let reward = self
+            .pool(pool_id)?
+            .rewards
+            .get(&token)
+            .ok_or(Error::RewardTokenNotFound)?;
+
+        if reward.distribution_time == 0 {
+            return Err(Error::ZeroTime);
+        }
+
+        self.check_funds(&token, 0, amount)?;
+        self.allocate(&token, 0, amount);
+        self.pool_mut(pool_id)?.notify_reward_amount(&token, amount);
+
+        Ok(StakingEvent::RewardNotified { token, amount })
+    }
+
+    /// Sets the fixed annual rate paid in the reward token on the stakers' effective balances
+    /// Arguments:
+    /// `token`: the reward token
+    /// `kind`: the kind of the reward token
+    /// `rate`: the annual rate in basis points, zero disables the interest
+    /// `coverage`: the period ahead for which the funds must cover the interest
+    fn set_fixed_apr(
+        &mut self,
+        pool_id: PoolId,
+        token: ActorId,
+        kind: TokenKind,
+        rate: u32,
+        coverage: u64,
+    ) -> Result<StakingEvent, Error> {
+        self.check_role(Role::RewardManager)?;
+        self.check_token_kind(&token, kind)?;
+
+        let pool = self.pool(pool_id)?;
+        let (current_rate, current_coverage) = pool
+            .rewards
+            .get(&token)
+            .map_or((0, 0), |reward| (reward.apr, reward.apr_coverage));
+        let extra = interest(pool.total_staked, rate, coverage).saturating_sub(interest(
+            pool.total_staked,
+            current_rate,
+            current_coverage,
+        ));
+
+        self.check_reserve(&token, extra)?;
+        self.token_kinds.insert(token, kind);
+        self.pool_mut(pool_id)?
+            .set_fixed_apr(&token, rate, coverage);
+
+        Ok(StakingEvent::FixedAprUpdated)
+    }
+
+    /// Appends the epoch to the reward schedule of the token
+    /// The token is added to the pool if it isn't there yet
+    /// Arguments:
+    /// `token`: the reward token
+    /// `kind`: the kind of the reward token
+    /// `epoch`: the epoch, which can't start in the past
+    fn add_epoch(
+        &mut self,
+        pool_id: PoolId,
+        token: ActorId,
+        kind: TokenKind,
+        epoch: Epoch,
+    ) -> Result<StakingEvent, Error> {
+        self.check_role(Role::RewardManager)?;
+        self.check_token_kind(&token, kind)?;
+
+        if epoch.amount == 0 {
+            return Err(Error::ZeroReward);
+        }
+
+        if epoch.start < exec::block_timestamp() || epoch.end <= epoch.start {
+            return Err(Error::InvalidEpoch);
+        }
+
+        self.pool(pool_id)?;
+        self.check_funds(&token, 0, epoch.amount)?;
+        self.allocate(&token, 0, epoch.amount);
+        self.token_kinds.insert(token, kind);
+
+        self.pool_mut(pool_id)?
+            .rewards
+            .entry(token)
+            .or_default()
+            .epochs
+            .push(epoch.clone());
+
+        Ok(StakingEvent::EpochAdded { token, epoch })
+    }
+
+    /// Removes the epoch that hasn't started yet from the reward schedule of the token
+    /// Arguments:
+    /// `token`: the reward token
+    /// `index`: the index of the epoch in the schedule
+    fn cancel_epoch(
+        &mut self,
+        pool_id: PoolId,
+        token: ActorId,
+        index: u32,
+    ) -> Result<StakingEvent, Error> {
+        self.check_role(Role::RewardManager)?;
+
+        let reward = self
+            .pool_mut(pool_id)?
+            .rewards
+            .get_mut(&token)
+            .ok_or(Error::RewardTokenNotFound)?;
+        let index = index as usize;
+        let epoch = reward.epochs.get(index).ok_or(Error::EpochNotFound)?;
+
+        if epoch.start <= exec::block_timestamp() {
+            return Err(Error::EpochStarted);
+        }
+
+        let epoch = reward.epochs.remove(index);
+        self.allocate(&token, epoch.amount, 0);
+
+        Ok(StakingEvent::EpochCancelled { token, epoch })
+    }
+
+    /// Moves the reward tokens from the funder to the program
+    /// Arguments:
+    /// `token`: the reward token
+    /// `kind`: the kind of the reward token
+    /// `amount`: the number of tokens
+    async fn fund_rewards(
+        &mut self,
+        token: ActorId,
+        kind: TokenKind,
+        amount: u128,
+    ) -> Result<StakingEvent, Error> {
+        if amount == 0 {
+            return Err(Error::ZeroAmount);
+        }
+
+        self.check_token_kind(&token, kind)?;
+
+        // The kind of a new token is only kept if the transfer succeeds
+        let is_known = self.token_kinds.insert(token, kind).is_some();
+
+        if let Err(error) = self
+            .transfer_tokens(&token, &msg::source(), &exec::program_id(), amount)
+            .await
+        {
+            if !is_known {
+                self.token_kinds.remove(&token);
+            }
+
+            return Err(error);
+        }
+
+        let funds = self.funds.entry(token).or_default();
+        funds.funded = funds.funded.saturating_add(amount);
+
+        Ok(StakingEvent::RewardsFunded { token, amount })
+    }
+
+    /// Updates the staking pool.
+    /// Sets the reward to be distributed within distribution time
+    /// The staking token can only change while the pool holds none of it
+    /// param 'config' - updated configuration
+    fn update_staking(
+        &mut self,
+        pool_id: PoolId,
+        config: InitStaking,
+    ) -> Result<StakingEvent, Error> {
+        self.check_role(Role::RewardManager)?;
+
+        let pool = self.pool(pool_id)?;
+
+        if config.staking_token_address != pool.staking_token_address && !pool.holds_no_stake() {
+            return Err(Error::PoolNotEmpty);
+        }
+
+        let released = pool.unproduced(&config.reward_token_address);
+        check_config(&config)?;
+        self.check_token_kinds(&config)?;
+        self.check_funds(&config.reward_token_address, released, config.reward_total)?;
+        self.allocate(&config.reward_token_address, released, config.reward_total);
+        self.register_token_kinds(&config);
+        self.pool_mut(pool_id)?.update(config);
+
+        Ok(StakingEvent::Updated)
+    }
+
+    /// Sets the lock terms and their reward multipliers
+    /// Arguments:
+    /// `terms`: pairs of a lock term and its multiplier in basis points
+    fn set_lock_terms(
+        &mut self,
+        pool_id: PoolId,
+        terms: Vec<(u64, u32)>,
+    ) -> Result<StakingEvent, Error> {
+        self.check_role(Role::Admin)?;
+
+        if terms
+            .iter()
+            .any(|(term, multiplier)| *term == 0 || *multiplier < BASIS_POINTS)
+        {
+            return Err(Error::InvalidLockTerm);
+        }
+
+        self.pool_mut(pool_id)?.lock_terms = terms.into_iter().collect();
+
+        Ok(StakingEvent::LockTermsUpdated)
+    }
+
+    /// Stakes the tokens
+    /// If the pool has positions enabled, the tokens are credited to a new position
+    /// owned by the beneficiary
+    /// Arguments:
+    /// `beneficiary`: the staker credited with the tokens taken from the sender
+    /// `amount`: the number of tokens for the stake
+    /// `term`: the lock term, if the tokens are locked for a reward multiplier
+    /// `locked_until`: the time before which the beneficiary can't take the tokens out
+    async fn stake(
+        &mut self,
+        pool_id: PoolId,
+        beneficiary: ActorId,
+        amount: u128,
+        term: Option<u64>,
+        locked_until: Option<u64>,
+    ) -> Result<StakingEvent, Error> {
+        if amount == 0 {
+            return Err(Error::ZeroAmount);
+        }
+
+        let pool = self.pool(pool_id)?;
+
+        if pool.share_token.is_some() && (term.is_some() || locked_until.is_some()) {
+            return Err(Error::ShareMode);
+        }
+
+        let multiplier = match term {
+            Some(term) => *pool.lock_terms.get(&term).ok_or(Error::LockTermNotFound)?,
+            None => BASIS_POINTS,
+        };
+
+        let token_address = pool.staking_token_address;
+        let effective_amount = amount.saturating_mul(multiplier.into()) / BASIS_POINTS as u128;
+
+        self.check_stake_reserve(pool_id, effective_amount)?;
+
+        self.transfer_tokens(&token_address, &msg::source(), &exec::program_id(), amount)
+            .await?;
+
+        if let Err(error) = self.mint_shares(pool_id, &beneficiary, amount).await {
+            // The stake never exists without its shares, so the tokens are returned,
+            // and the attached value is returned with the reply
+            if self.token_kind(&token_address) != TokenKind::Native
+                && self
+                    .transfer_tokens(&token_address, &exec::program_id(), &msg::source(), amount)
+                    .await
+                    .is_err()
+            {
+                self.pool_mut(pool_id)?.owe_refund(&msg::source(), amount);
+            }
+
+            return Err(error);
+        }
+
+        let position = if self.pool(pool_id)?.positions {
+            let id = self.next_position_id;
+            self.next_position_id = self.next_position_id.saturating_add(1);
+            self.positions.insert(
+                id,
+                Position {
+                    pool: pool_id,
+                    owner: beneficiary,
+                },
+            );
+            Some(id)
+        } else {
+            None
+        };
+
+        let staker = position.map_or(beneficiary, position_key);
+        let pool = self.pool_mut(pool_id)?;
+        let event = pool.deposit(&staker, amount, multiplier, term);
+
+        if let Some(unlock_time) = locked_until.filter(|time| *time > exec::block_timestamp()) {
+            let staker = pool.stakers.entry(staker).or_default();
+            staker.locks.push(Lock {
+                amount,
+                unlock_time,
+            });
+        }
+
+        match position {
+            Some(id) => Ok(StakingEvent::PositionMinted { id, amount }),
+            None => Ok(event),
+        }
+    }
+
+    /// Stakes the sender's tokens on behalf of the beneficiary
+    /// The tokens can't be locked for more than a year
+    /// Arguments:
+    /// `beneficiary`: the staker credited with the tokens
+    /// `amount`: the number of tokens for the stake
+    /// `locked_until`: the time before which the beneficiary can't take the tokens out
+    async fn stake_for(
+        &mut self,
+        pool_id: PoolId,
+        beneficiary: ActorId,
+        amount: u128,
+        locked_until: Option<u64>,
+    ) -> Result<StakingEvent, Error> {
+        check_recipient(&beneficiary)?;
+
+        if locked_until.map_or(false, |time| {
+            time > exec::block_timestamp().saturating_add(YEAR)
+        }) {
+            return Err(Error::InvalidLockTerm);
+        }
+
+        let event = self
+            .stake(pool_id, beneficiary, amount, None, locked_until)
+            .await?;
+
+        if let StakingEvent::PositionMinted { .. } = event {
+            return Ok(event);
+        }
+
+        Ok(StakingEvent::StakedFor {
+            beneficiary,
+            amount,
+            locked_until,
+        })
+    }
+
+    ///Sends reward to the staker
+    /// The share of the penalties is paid along with the reward in the staking token
+    /// The reward is marked as distributed before the transfer and restored if it fails,
+    /// so concurrent claims for the same staker can't pay it twice
+    /// Arguments:
+    /// `staker`: the staker whose reward is claimed
+    /// `recipient`: the account receiving the reward
+    /// `token`: the reward token to claim, all reward tokens of the pool if `None`
+    async fn send_reward(
+        &mut self,
+        pool_id: PoolId,
+        staker: ActorId,
+        recipient: ActorId,
+        token: Option<ActorId>,
+    ) -> Result<StakingEvent, Error> {
+        let pool = self.pool_mut(pool_id)?;
+
+        pool.update_reward();
+        pool.checkpoint(&staker);
+
+        let staking_token = pool.staking_token_address;
+        let with_penalties = token.map_or(true, |token| token == staking_token);
+
+        let tokens = match token {
+            // The staking token may only carry the penalties
+            Some(token) if with_penalties && !pool.rewards.contains_key(&token) => vec![],
+            Some(token) => vec![token],
+            None => pool.rewards.keys().copied().collect(),
+        };
+
+        let mut rewards = Vec::with_capacity(tokens.len());
+
+        for token in tokens {
+            let reward = pool.calc_reward(&staker, &token)?;
+
+            if reward > 0 {
+                rewards.push((token, reward));
+            }
+        }
+
+        let penalty_reward = if with_penalties {
+            pool.calc_penalty_reward(&staker)?
+        } else {
+            0
+        };
+
+        let payouts = with_penalties_paid(&rewards, staking_token, penalty_reward);
+
+        if payouts.is_empty() {
+            return Err(Error::ZeroReward);
+        }
+
+        if let Some(staker) = pool.stakers.get_mut(&staker) {
+            for (token, reward) in &rewards {
+                let staker_reward = staker.rewards.entry(*token).or_default();
+                staker_reward.distributed = staker_reward.distributed.saturating_add(*reward);
+            }
+
+            staker.penalty_reward.distributed = staker
+                .penalty_reward
+                .distributed
+                .saturating_add(penalty_reward);
+        }
+
+        for (index, (token, payout)) in payouts.iter().enumerate() {
+            if let Err(error) = self
+                .transfer_tokens(token, &exec::program_id(), &recipient, *payout)
+                .await
+            {
+                let unpaid: Vec<_> = payouts[index..].iter().map(|(token, _)| *token).collect();
+
+                if let Some(staker) = self.pool_mut(pool_id)?.stakers.get_mut(&staker) {
+                    for (token, reward) in
+                        rewards.iter().filter(|(token, _)| unpaid.contains(token))
+                    {
+                        let staker_reward = staker.rewards.entry(*token).or_default();
+                        staker_reward.distributed =
+                            staker_reward.distributed.saturating_sub(*reward);
+                    }
+
+                    if unpaid.contains(&staking_token) {
+                        staker.penalty_reward.distributed = staker
+                            .penalty_reward
+                            .distributed
+                            .saturating_sub(penalty_reward);
+                    }
+                }
+
+                return Err(error);
+            }
+
+            // The penalties aren't taken from the funded rewards
+            if let Some((_, reward)) = rewards
+                .iter()
+                .find(|(reward_token, _)| reward_token == token)
+            {
+                let funds = self.funds.entry(*token).or_default();
+                funds.claimed = funds.claimed.saturating_add(*reward);
+            }
+        }
+
+        Ok(StakingEvent::Reward(payouts))
+    }
+
+    /// Claims the reward of the staker that approved the message source as a claimer
+    /// The reward goes to the staker's reward recipient, if set, or to the staker
+    /// Arguments:
+    /// `staker`: the staker whose reward is claimed
+    /// `token`: the reward token to claim, all reward tokens of the pool if `None`
+    async fn claim_for(
+        &mut self,
+        pool_id: PoolId,
+        staker: ActorId,
+        token: Option<ActorId>,
+    ) -> Result<StakingEvent, Error> {
+        let approved = self
+            .claimers
+            .get(&staker)
+            .map_or(false, |claimers| claimers.contains(&msg::source()));
+
+        if !approved {
+            return Err(Error::NotClaimer);
+        }
+
+        let recipient = self.reward_recipient(&staker);
+        self.send_reward(pool_id, staker, recipient, token).await
+    }
+
+    /// Returns the account receiving the staker's reward
+    fn reward_recipient(&self, staker: &ActorId) -> ActorId {
+        self.reward_recipients
+            .get(staker)
+            .copied()
+            .unwrap_or(*staker)
+    }
+
+    /// Allows the operator to claim the message source's rewards
+    /// Arguments:
+    /// `operator`: the approved claimer
+    fn approve_claimer(&mut self, operator: ActorId) -> Result<StakingEvent, Error> {
+        if operator.is_zero() {
+            return Err(Error::ZeroAddress);
+        }
+
+        self.claimers
+            .entry(msg::source())
+            .or_default()
+            .insert(operator);
+
+        Ok(StakingEvent::ClaimerApproved(operator))
+    }
+
+    /// Revokes the operator's approval to claim the message source's rewards
+    /// Arguments:
+    /// `operator`: the revoked claimer
+    fn revoke_claimer(&mut self, operator: ActorId) -> Result<StakingEvent, Error> {
+        let claimers = self
+            .claimers
+            .get_mut(&msg::source())
+            .ok_or(Error::NotClaimer)?;
+
+        if !claimers.remove(&operator) {
+            return Err(Error::NotClaimer);
+        }
+
+        if claimers.is_empty() {
+            self.claimers.remove(&msg::source());
+        }
+
+        Ok(StakingEvent::ClaimerRevoked(operator))
+    }
+
+    /// Sets the account receiving the message source's rewards claimed by `GetReward`
+    /// and the claimers
+    /// Arguments:
+    /// `recipient`: the recipient, the staker themselves if `None`
+    fn set_reward_recipient(&mut self, recipient: Option<ActorId>) -> Result<StakingEvent, Error> {
+        match recipient {
+            Some(recipient) if recipient.is_zero() => return Err(Error::ZeroAddress),
+            Some(recipient) => {
+                self.reward_recipients.insert(msg::source(), recipient);
+            }
+            None => {
+                self.reward_recipients.remove(&msg::source());
+            }
+        }
+
+        Ok(StakingEvent::RewardRecipientUpdated(recipient))
+    }
+
+    /// Restakes the reward of the staker
+    /// The shares of the restaked reward are minted first,
+    /// and the reward produced while they are minted stays claimable
+    /// The restaked reward is a new stake, so the reserve must cover the interest on it
+    async fn compound(&mut self, pool_id: PoolId, staker: ActorId) -> Result<StakingEvent, Error> {
+        let pool = self.pool_mut(pool_id)?;
+        let token = pool.staking_token_address;
+
+        if !pool.rewards.contains_key(&token) {
+            return Err(Error::RewardTokenNotFound);
+        }
+
+        pool.update_reward();
+        let reward = pool.calc_restakable(&staker)?;
+        let is_liquid = pool.share_token.is_some();
+
+        if reward == 0 {
+            return Err(Error::ZeroReward);
+        }
+
+        self.check_stake_reserve(pool_id, reward)?;
+
+        let limit = if is_liquid {
+            self.mint_shares(pool_id, &staker, reward).await?;
+            Some(reward)
+        } else {
+            None
+        };
+
+        let (amount, claimed) = self.pool_mut(pool_id)?.compound(&staker, limit)?;
+
+        let funds = self.funds.entry(token).or_default();
+        funds.claimed = funds.claimed.saturating_add(claimed);
+
+        Ok(StakingEvent::Compounded { amount })
+    }
+
+    /// Withdraws the staked the tokens
+    /// Arguments:
+    /// `staker`: the staker taking the tokens out
+    /// `recipient`: the account receiving the tokens
+    /// `amount`: the number of withdrawn tokens
+    async fn withdraw(
+        &mut self,
+        pool_id: PoolId,
+        staker: ActorId,
+        recipient: ActorId,
+        amount: u128,
+    ) -> Result<StakingEvent, Error> {
+        if amount == 0 {
+            return Err(Error::ZeroAmount);
+        }
+
+        let pool = self.pool_mut(pool_id)?;
+
+        if pool.unbonding_period > 0 {
+            return Err(Error::UnbondingRequired);
+        }
+
+        pool.check_unlocked(&staker, amount)?;
+        let penalty = pool.calc_penalty(&staker, amount);
+
+        let token_address = pool.staking_token_address;
+
+        self.burn_shares(pool_id, &staker, amount).await?;
+
+        if amount > penalty {
+            if let Err(error) = self
+                .transfer_tokens(
+                    &token_address,
+                    &exec::program_id(),
+                    &recipient,
+                    amount - penalty,
+                )
+                .await
+            {
+                // The stake stays, so the shares are given back
+                if self.mint_shares(pool_id, &staker, amount).await.is_err() {
+                    self.pool_mut(pool_id)?.owe_shares(&staker, amount);
+                }
+
+                return Err(error);
+            }
+        }
+
+        let pool = self.pool_mut(pool_id)?;
+
+        pool.update_reward();
+        pool.decrease_weight(&staker, amount);
+
+        let staker_info = pool.stakers.get_mut(&staker).ok_or(Error::StakerNotFound)?;
+        subtract_balance(staker_info, amount);
+        pool.checkpoint(&staker);
+        pool.charge_penalty(penalty);
+
+        // If the transfer fails, the penalties are sent with the next ones
+        self.send_penalties(pool_id).await.ok();
+
+        Ok(StakingEvent::Withdrawn { amount, penalty })
+    }
+
+    /// Withdraws the staker's unlocked tokens and matured unbonding chunks
+    /// The rewards are forfeited, so the reward tokens aren't called
+    /// The locked tokens and the unbonding chunks that haven't matured stay in the pool,
+    /// and the early withdrawal penalty is charged like on `Withdraw`
+    /// In the pools with an unbonding period the unlocked tokens start unbonding
+    /// and only the matured chunks are sent
+    /// The shares of the withdrawn balance are burnt, the unbonding tokens have none
+    /// Arguments:
+    /// `staker`: the staker taking the tokens out
+    /// `recipient`: the account receiving the tokens
+    async fn emergency_withdraw(
+        &mut self,
+        pool_id: PoolId,
+        staker: ActorId,
+        recipient: ActorId,
+    ) -> Result<StakingEvent, Error> {
+        let pool = self.pool_mut(pool_id)?;
+        let staker_info = pool.stakers.get(&staker).ok_or(Error::StakerNotFound)?;
+
+        let now = exec::block_timestamp();
+        let locked: u128 = staker_info
+            .locks
+            .iter()
+            .filter(|lock| lock.unlock_time > now)
+            .map(|lock| lock.amount)
+            .sum();
+        let shares = staker_info.balance.saturating_sub(locked);
+        let is_unbonding = !staker_info.unbonding.is_empty();
+        let penalty = pool.calc_penalty(&staker, shares);
+
+        // The matured chunks are taken out before the transfer, like on `ClaimUnbonded`
+        let chunks = pool.take_matured(&staker)?;
+        let matured: u128 = chunks.iter().map(|chunk| chunk.amount).sum();
+
+        if shares == 0 && matured == 0 {
+            pool.restore_unbonding(&staker, chunks);
+
+            return Err(if locked > 0 {
+                Error::LockedTokens
+            } else if is_unbonding {
+                Error::UnbondingNotMatured
+            } else {
+                Error::ZeroAmount
+            });
+        }
+
+        let amount = if pool.unbonding_period > 0 {
+            matured
+        } else {
+            matured + shares - penalty
+        };
+        let token_address = pool.staking_token_address;
+
+        if let Err(error) = self.burn_shares(pool_id, &staker, shares).await {
+            self.pool_mut(pool_id)?.restore_unbonding(&staker, chunks);
+
+            return Err(error);
+        }
+
+        if amount > 0 {
+            if let Err(error) = self
+                .transfer_tokens(&token_address, &exec::program_id(), &recipient, amount)
+                .await
+            {
+                self.pool_mut(pool_id)?.restore_unbonding(&staker, chunks);
+
+                if self.mint_shares(pool_id, &staker, shares).await.is_err() {
+                    self.pool_mut(pool_id)?.owe_shares(&staker, shares);
+                }
+
+                return Err(error);
+            }
+        }
+
+        let owner = self.owner;
+        self.pool_mut(pool_id)?
+            .emergency_exit(&staker, shares, penalty, &owner);
+
+        // If the transfer fails, the penalties are sent with the next ones
+        self.send_penalties(pool_id).await.ok();
+
+        Ok(StakingEvent::EmergencyWithdrawn(amount))
+    }
+
+    /// Pauses the chosen groups of actions, the groups paused before stay paused
+    /// The rewards keep accruing while the claims are paused
+    /// Arguments:
+    /// `mask`: the groups of actions to pause
+    fn pause(&mut self, mask: ActionMask) -> Result<StakingEvent, Error> {
+        self.check_role(Role::Pauser)?;
+
+        self.paused = self.paused.union(mask);
+
+        Ok(StakingEvent::Paused(self.paused))
+    }
+
+    /// Resumes the chosen groups of actions, the other groups stay paused
+    /// Arguments:
+    /// `mask`: the groups of actions to resume
+    fn unpause(&mut self, mask: ActionMask) -> Result<StakingEvent, Error> {
+        self.check_role(Role::Pauser)?;
+
+        self.paused = self.paused.difference(mask);
+
+        Ok(StakingEvent::Unpaused(self.paused))
+    }
+
+    /// Checks that the message source can grant and revoke the role
+    /// Only the owner manages the `Admin` role, admins manage the others
+    /// Arguments:
+    /// `role`: the managed role
+    fn check_role_manager(&self, role: Role) -> Result<(), Error> {
+        if role == Role::Admin && msg::source() != self.owner {
+            return Err(Error::NotOwner);
+        }
+
+        self.check_role(Role::Admin)
+    }
+
+    /// Grants the role to the actor
+    /// Arguments:
+    /// `actor`: the actor receiving the role
+    /// `role`: the granted role
+    fn grant_role(&mut self, actor: ActorId, role: Role) -> Result<StakingEvent, Error> {
+        self.check_role_manager(role)?;
+
+        self.roles.entry(actor).or_default().insert(role);
+
+        Ok(StakingEvent::RoleGranted { actor, role })
+    }
+
+    /// Revokes the role from the actor
+    /// Arguments:
+    /// `actor`: the actor losing the role
+    /// `role`: the revoked role
+    fn revoke_role(&mut self, actor: ActorId, role: Role) -> Result<StakingEvent, Error> {
+        self.check_role_manager(role)?;
+
+        if let Some(roles) = self.roles.get_mut(&actor) {
+            roles.remove(&role);
+
+            if roles.is_empty() {
+                self.roles.remove(&actor);
+            }
+        }
+
+        Ok(StakingEvent::RoleRevoked { actor, role })
+    }
+
+    /// Proposes a new owner, who has to accept the ownership
+    /// Arguments:
+    /// `new_owner`: the proposed owner
+    fn transfer_ownership(&mut self, new_owner: ActorId) -> Result<StakingEvent, Error> {
+        if msg::source() != self.owner {
+            return Err(Error::NotOwner);
+        }
+
+        self.pending_owner = Some(new_owner);
+
+        Ok(StakingEvent::OwnershipTransferStarted(new_owner))
+    }
+
+    /// Accepts the ownership proposed by the current owner
+    fn accept_ownership(&mut self) -> Result<StakingEvent, Error> {
+        if self.pending_owner != Some(msg::source()) {
+            return Err(Error::NotPendingOwner);
+        }
+
+        self.owner = msg::source();
+        self.pending_owner = None;
+
+        Ok(StakingEvent::OwnershipTransferred(self.owner))
+    }
+
+    /// Leaves the program without an owner, so the owner's actions become unavailable
+    fn renounce_ownership(&mut self) -> Result<StakingEvent, Error> {
+        if msg::source() != self.owner {
+            return Err(Error::NotOwner);
+        }
+
+        self.owner = ActorId::zero();
+        self.pending_owner = None;
+
+        Ok(StakingEvent::OwnershipRenounced)
+    }
+
+    /// Checks that the action isn't paused
+    /// Arguments:
+    /// `action`: the incoming action
+    fn check_paused(&self, action: &StakingAction) -> Result<(), Error> {
+        let paused = match action {
+            StakingAction::Stake { .. }
+            | StakingAction::StakeLocked { .. }
+            | StakingAction::StakeFor { .. }
+            | StakingAction::VaultDeposit { .. }
+            | StakingAction::Rebond { .. } => self.paused.stake,
+            StakingAction::Withdraw { .. }
+            | StakingAction::RequestUnstake { .. }
+            | StakingAction::ClaimUnbonded { .. }
+            | StakingAction::TransferStake { .. }
+            | StakingAction::VaultWithdraw { .. }
+            | StakingAction::SettleOwed { .. } => self.paused.withdraw,
+            StakingAction::GetReward { .. }
+            | StakingAction::GetRewardTo { .. }
+            | StakingAction::ClaimFor { .. }
+            | StakingAction::ClaimForfeited { .. } => self.paused.claim,
+            StakingAction::Compound { .. } => self.paused.claim || self.paused.stake,
+            StakingAction::CreatePool(_)
+            | StakingAction::UpdateStaking { .. }
+            | StakingAction::NotifyRewardAmount { .. }
+            | StakingAction::SetFixedApr { .. }
+            | StakingAction::AddEpoch { .. }
+            | StakingAction::CancelEpoch { .. } => self.paused.update,
+            StakingAction::EmergencyWithdraw { .. } => self.paused.emergency_withdraw,
+            StakingAction::Position { action, .. } => match action {
+                PositionAction::Withdraw { .. }
+                | PositionAction::RequestUnstake { .. }
+                | PositionAction::ClaimUnbonded => self.paused.withdraw,
+                PositionAction::Rebond { .. } => self.paused.stake,
+                PositionAction::GetReward { .. } => self.paused.claim,
+                PositionAction::Compound => self.paused.claim || self.paused.stake,
+                PositionAction::EmergencyWithdraw => self.paused.emergency_withdraw,
+            },
+            StakingAction::TransferPosition { .. } => self.paused.claim,
+            _ => false,
+        };
+
+        if paused {
+            return Err(Error::Paused);
+        }
+
+        Ok(())
+    }
+
+    /// Sets the reward weights of the stakers depending on their balances
+    /// The stakers are moved to the new tiers when they interact with the pool
+    /// Arguments:
+    /// `tiers`: the minimum balances of the tiers and their weights in basis points
+    fn set_tiers(
+        &mut self,
+        pool_id: PoolId,
+        tiers: Vec<(u128, u32)>,
+    ) -> Result<StakingEvent, Error> {
+        self.check_role(Role::Admin)?;
+
+        if tiers.iter().any(|(_min_balance, weight)| *weight == 0) {
+            return Err(Error::InvalidTier);
+        }
+
+        self.pool_mut(pool_id)?.tiers = tiers.into_iter().collect();
+
+        Ok(StakingEvent::TiersUpdated)
+    }
+
+    /// Sets the multiplier growing with the continuous staking time
+    /// The stakers get the multiplier they've earned when they interact with the pool
+    /// Arguments:
+    /// `config`: the loyalty multiplier steps and cap, `None` disables it
+    fn set_loyalty(
+        &mut self,
+        pool_id: PoolId,
+        config: Option<LoyaltyConfig>,
+    ) -> Result<StakingEvent, Error> {
+        self.check_role(Role::Admin)?;
+
+        if matches!(&config, Some(config) if config.step == 0) {
+            return Err(Error::InvalidLoyalty);
+        }
+
+        self.pool_mut(pool_id)?.loyalty = config;
+
+        Ok(StakingEvent::LoyaltyUpdated)
+    }
+
+    /// Switches the minting of transferable positions for the new deposits
+    /// The mode can only change while the pool has no stakers
+    /// Arguments:
+    /// `enabled`: whether the deposits mint positions
+    fn set_position_mode(&mut self, pool_id: PoolId, enabled: bool) -> Result<StakingEvent, Error> {
+        self.check_role(Role::Admin)?;
+
+        let pool = self.pool_mut(pool_id)?;
+
+        if !pool.stakers.is_empty() {
+            return Err(Error::PoolNotEmpty);
+        }
+
+        if enabled && pool.share_token.is_some() {
+            return Err(Error::ShareMode);
+        }
+
+        pool.positions = enabled;
+
+        Ok(StakingEvent::PositionModeUpdated)
+    }
+
+    /// Returns the position if the message source owns it
+    /// Arguments:
+    /// `id`: the position id
+    fn owned_position(&self, id: PositionId) -> Result<Position, Error> {
+        let position = *self.positions.get(&id).ok_or(Error::PositionNotFound)?;
+
+        if position.owner != msg::source() {
+            return Err(Error::NotPositionOwner);
+        }
+
+        Ok(position)
+    }
+
+    /// Runs the owner's operation on the position
+    /// The tokens and rewards are sent to the owner
+    /// The position is burnt once its staker has nothing left in the pool
+    /// Arguments:
+    /// `id`: the position id
+    /// `action`: the operation
+    async fn position_action(
+        &mut self,
+        id: PositionId,
+        action: PositionAction,
+    ) -> Result<StakingEvent, Error> {
+        let Position { pool, owner } = self.owned_position(id)?;
+        let staker = position_key(id);
+
+        let event = match action {
+            PositionAction::Withdraw { amount } => self.withdraw(pool, staker, owner, amount).await,
+            PositionAction::RequestUnstake { amount } => {
+                self.request_unstake(pool, staker, amount).await
+            }
+            PositionAction::ClaimUnbonded => self.claim_unbonded(pool, staker, owner).await,
+            PositionAction::Rebond { index } => self.rebond(pool, staker, index).await,
+            PositionAction::Compound => self.compound(pool, staker).await,
+            PositionAction::GetReward { token } => {
+                let recipient = self.reward_recipient(&owner);
+                self.send_reward(pool, staker, recipient, token).await
+            }
+            PositionAction::EmergencyWithdraw => self.emergency_withdraw(pool, staker, owner).await,
+        }?;
+
+        if self.pool_mut(pool)?.remove_if_empty(&staker) {
+            self.positions.remove(&id);
+        }
+
+        Ok(event)
+    }
+
+    /// Transfers the position to the new owner
+    /// The pending rewards are paid to the previous owner first
+    /// Arguments:
+    /// `id`: the position id
+    /// `to`: the new owner
+    async fn transfer_position(
+        &mut self,
+        id: PositionId,
+        to: ActorId,
+    ) -> Result<StakingEvent, Error> {
+        check_recipient(&to)?;
+
+        let Position { pool, owner } = self.owned_position(id)?;
+        let recipient = self.reward_recipient(&owner);
+
+        match self
+            .send_reward(pool, position_key(id), recipient, None)
+            .await
+        {
+            Ok(_) | Err(Error::ZeroReward) | Err(Error::StakerNotFound) => {}
+            Err(error) => return Err(error),
+        }
+
+        if let Some(position) = self.positions.get_mut(&id) {
+            position.owner = to;
+        }
+
+        Ok(StakingEvent::PositionTransferred {
+            id,
+            from: owner,
+            to,
+        })
+    }
+
+    /// Sets where the rewards forfeited by emergency withdrawals go
+    /// Arguments:
+    /// `destination`: the remaining stakers or the owner
+    fn set_forfeit_destination(
+        &mut self,
+        pool_id: PoolId,
+        destination: ForfeitDestination,
+    ) -> Result<StakingEvent, Error> {
+        self.check_role(Role::Admin)?;
+
+        self.pool_mut(pool_id)?.forfeit_destination = destination;
+
+        Ok(StakingEvent::ForfeitDestinationUpdated)
+    }
+
+    /// Sends the rewards forfeited to the owner
+    /// Only the owner claims them, the admins can't
+    /// The rewards are taken before the transfers and restored if they fail
+    /// Arguments:
+    /// `recipient`: the account receiving the rewards
+    async fn claim_forfeited(
+        &mut self,
+        pool_id: PoolId,
+        recipient: ActorId,
+    ) -> Result<StakingEvent, Error> {
+        if msg::source() != self.owner {
+            return Err(Error::NotOwner);
+        }
+
+        if recipient.is_zero() {
+            return Err(Error::ZeroAddress);
+        }
+
+        let pool = self.pool_mut(pool_id)?;
+        let staking_token = pool.staking_token_address;
+        let rewards: Vec<_> = mem::take(&mut pool.forfeited).into_iter().collect();
+        let penalties = mem::take(&mut pool.forfeited_penalties);
+        let payouts = with_penalties_paid(&rewards, staking_token, penalties);
+
+        if payouts.is_empty() {
+            return Err(Error::ZeroReward);
+        }
+
+        for (index, (token, payout)) in payouts.iter().enumerate() {
+            if let Err(error) = self
+                .transfer_tokens(token, &exec::program_id(), &recipient, *payout)
+                .await
+            {
+                let unpaid: Vec<_> = payouts[index..].iter().map(|(token, _)| *token).collect();
+                let pool = self.pool_mut(pool_id)?;
+
+                for (token, reward) in rewards.iter().filter(|(token, _)| unpaid.contains(token)) {
+                    let kept = pool.forfeited.entry(*token).or_default();
+                    *kept = kept.saturating_add(*reward);
+                }
+
+                if unpaid.contains(&staking_token) {
+                    pool.forfeited_penalties = pool.forfeited_penalties.saturating_add(penalties);
+                }
+
+                return Err(error);
+            }
+
+            if let Some((_, reward)) = rewards
+                .iter()
+                .find(|(reward_token, _)| reward_token == token)
+            {
+                let funds = self.funds.entry(*token).or_default();
+                funds.claimed = funds.claimed.saturating_add(*reward);
+            }
+        }
+
+        Ok(StakingEvent::ForfeitedClaimed(payouts))
+    }
+
+    /// Sends the tokens and mints the shares owed to the account after the failed rollbacks
+    /// Anyone can retry it, since they only go to the account they're owed to
+    /// The debts are taken before the transfers and restored if they fail
+    /// Arguments:
+    /// `account`: the account the tokens and shares are owed to
+    async fn settle_owed(
+        &mut self,
+        pool_id: PoolId,
+        account: ActorId,
+    ) -> Result<StakingEvent, Error> {
+        let pool = self.pool_mut(pool_id)?;
+        let token_address = pool.staking_token_address;
+        let refund = pool.owed_refunds.remove(&account).unwrap_or_default();
+        let shares = pool.owed_shares.remove(&account).unwrap_or_default();
+
+        if refund == 0 && shares == 0 {
+            return Err(Error::ZeroAmount);
+        }
+
+        if refund > 0 {
+            if let Err(error) = self
+                .transfer_tokens(&token_address, &exec::program_id(), &account, refund)
+                .await
+            {
+                let pool = self.pool_mut(pool_id)?;
+                pool.owe_refund(&account, refund);
+                pool.owe_shares(&account, shares);
+
+                return Err(error);
+            }
+        }
+
+        if shares > 0 {
+            if let Err(error) = self.mint_shares(pool_id, &account, shares).await {
+                self.pool_mut(pool_id)?.owe_shares(&account, shares);
+
+                return Err(error);
+            }
+        }
+
+        Ok(StakingEvent::OwedSettled {
+            account,
+            refund,
+            shares,
+        })
+    }
+
+    /// Sets the penalty for withdrawals before the holding period is over
+    /// Arguments:
+    /// `config`: the penalty configuration, `None` disables the penalty
+    fn set_penalty(
+        &mut self,
+        pool_id: PoolId,
+        config: Option<PenaltyConfig>,
+    ) -> Result<StakingEvent, Error> {
+        self.check_role(Role::Slasher)?;
+
+        if let Some(PenaltyConfig {
+            rate,
+            holding_period,
+            ..
+        }) = config
+        {
+            if rate > BASIS_POINTS || holding_period == 0 {
+                return Err(Error::InvalidPenalty);
+            }
+        }
+
+        self.pool_mut(pool_id)?.penalty = config;
+
+        Ok(StakingEvent::PenaltyUpdated)
+    }
+
+    /// Sends the penalties collected in the pool to the treasury
+    async fn send_penalties(&mut self, pool_id: PoolId) -> Result<(), Error> {
+        let pool = self.pool(pool_id)?;
+
+        let Some(PenaltyConfig {
+            destination: PenaltyDestination::Treasury(treasury),
+            ..
+        }) = pool.penalty
+        else {
+            return Ok(());
+        };
+
+        let amount = pool.unpaid_penalties;
+
+        if amount == 0 {
+            return Ok(());
+        }
+
+        let token_address = pool.staking_token_address;
+        self.transfer_tokens(&token_address, &exec::program_id(), &treasury, amount)
+            .await?;
+
+        let pool = self.pool_mut(pool_id)?;
+        pool.unpaid_penalties = pool.unpaid_penalties.saturating_sub(amount);
+
+        Ok(())
+    }
+
+    /// Sets the time the unstaked tokens are held before they can be claimed
+    /// The vault has no unbonding, so the period can't be set while it holds deposits
+    /// Arguments:
+    /// `period`: the unbonding period, `0` allows to withdraw the tokens at once
+    fn set_unbonding_period(
+        &mut self,
+        pool_id: PoolId,
+        period: u64,
+    ) -> Result<StakingEvent, Error> {
+        self.check_role(Role::Admin)?;
+
+        let pool = self.pool_mut(pool_id)?;
+
+        if period > 0 && pool.vault_total_shares > 0 {
+            return Err(Error::PoolNotEmpty);
+        }
+
+        pool.unbonding_period = period;
+
+        Ok(StakingEvent::UnbondingPeriodUpdated)
+    }
+
+    /// Starts unbonding of the staked tokens
+    /// Arguments:
+    /// `staker`: the staker unstaking the tokens
+    /// `amount`: the number of unstaked tokens
+    async fn request_unstake(
+        &mut self,
+        pool_id: PoolId,
+        staker: ActorId,
+        amount: u128,
+    ) -> Result<StakingEvent, Error> {
+        let pool = self.pool_mut(pool_id)?;
+
+        if pool.share_token.is_some() {
+            if amount == 0 {
+                return Err(Error::ZeroAmount);
+            }
+
+            pool.check_unlocked(&staker, amount)?;
+            self.burn_shares(pool_id, &staker, amount).await?;
+        }
+
+        let event = self.pool_mut(pool_id)?.request_unstake(&staker, amount)?;
+
+        // If the transfer fails, the penalties are sent with the next ones
+        self.send_penalties(pool_id).await.ok();
+
+        Ok(event)
+    }
+
+    /// Sends the tokens whose unbonding period is over to the staker
+    /// The matured chunks are taken out before the transfer and put back if it fails,
+    /// so concurrent claims for the same staker can't pay them twice
+    /// Arguments:
+    /// `staker`: the staker owning the unbonding tokens
+    /// `recipient`: the account receiving the tokens
+    async fn claim_unbonded(
+        &mut self,
+        pool_id: PoolId,
+        staker: ActorId,
+        recipient: ActorId,
+    ) -> Result<StakingEvent, Error> {
+        let pool = self.pool_mut(pool_id)?;
+        let token_address = pool.staking_token_address;
+        let matured = pool.take_matured(&staker)?;
+        let amount: u128 = matured.iter().map(|chunk| chunk.amount).sum();
+
+        if amount == 0 {
+            pool.restore_unbonding(&staker, matured);
+
+            return Err(Error::UnbondingNotMatured);
+        }
+
+        if let Err(error) = self
+            .transfer_tokens(&token_address, &exec::program_id(), &recipient, amount)
+            .await
+        {
+            self.pool_mut(pool_id)?.restore_unbonding(&staker, matured);
+
+            return Err(error);
+        }
+
+        Ok(StakingEvent::Withdrawn { amount, penalty: 0 })
+    }
+
+    /// Moves the message source's staked tokens to another account
+    /// Arguments:
+    /// `to`: the recipient
+    /// `amount`: the number of tokens
+    fn transfer_stake(
+        &mut self,
+        pool_id: PoolId,
+        to: ActorId,
+        amount: u128,
+    ) -> Result<StakingEvent, Error> {
+        if amount == 0 {
+            return Err(Error::ZeroAmount);
+        }
+
+        check_recipient(&to)?;
+
+        if to == msg::source() {
+            return Err(Error::SelfTransfer);
+        }
+
+        // The shares are transferred with the token itself
+        if self.pool(pool_id)?.share_token.is_some() {
+            return Err(Error::ShareMode);
+        }
+
+        self.pool_mut(pool_id)?
+            .transfer_stake(&msg::source(), &to, amount)
+    }
+
+    /// Cancels the unbonding chunk and stakes its tokens back
+    /// The reserve must cover the interest on the rebonded tokens like on a new stake
+    /// Arguments:
+    /// `staker`: the staker owning the unbonding tokens
+    /// `index`: the index of the chunk in the staker's unbonding queue
+    async fn rebond(
+        &mut self,
+        pool_id: PoolId,
+        staker: ActorId,
+        index: u32,
+    ) -> Result<StakingEvent, Error> {
+        let pool = self.pool(pool_id)?;
+        let amount = pool
+            .stakers
+            .get(&staker)
+            .ok_or(Error::StakerNotFound)?
+            .unbonding
+            .get(index as usize)
+            .ok_or(Error::UnbondingNotFound)?
+            .amount;
+        let is_liquid = pool.share_token.is_some();
+
+        self.check_stake_reserve(pool_id, amount)?;
+
+        if is_liquid {
+            self.mint_shares(pool_id, &staker, amount).await?;
+        }
+
+        self.pool_mut(pool_id)?.rebond(&staker, index)
+    }
+
+    /// Restakes the vault's reward, raising the exchange rate of its shares
+    /// The vault is available if the pool distributes the staking token
+    /// The reward stays pending while the reserve can't cover the interest on it
+    fn harvest(&mut self, pool_id: PoolId) -> Result<(), Error> {
+        let pool = self.pool_mut(pool_id)?;
+        let token = pool.staking_token_address;
+
+        if !pool.rewards.contains_key(&token) {
+            return Err(Error::RewardTokenNotFound);
+        }
+
+        // The shares of the liquid stake can't be minted to the vault
+        if pool.share_token.is_some() {
+            return Err(Error::ShareMode);
+        }
+
+        pool.update_reward();
+        let reward = pool.calc_restakable(&vault_key()).unwrap_or_default();
+
+        if reward == 0 || self.check_stake_reserve(pool_id, reward).is_err() {
+            return Ok(());
+        }
+
+        let (_amount, claimed) = self
+            .pool_mut(pool_id)?
+            .compound(&vault_key(), None)
+            .unwrap_or_default();
+
+        let funds = self.funds.entry(token).or_default();
+        funds.claimed = funds.claimed.saturating_add(claimed);
+
+        Ok(())
+    }
+
+    /// Deposits the tokens to the vault for its shares at the current exchange rate
+    /// The vault can't unbond, so it takes no deposits while the pool has an unbonding period
+    /// Arguments:
+    /// `amount`: the number of tokens
+    async fn vault_deposit(
+        &mut self,
+        pool_id: PoolId,
+        amount: u128,
+    ) -> Result<StakingEvent, Error> {
+        if amount == 0 {
+            return Err(Error::ZeroAmount);
+        }
+
+        self.harvest(pool_id)?;
+
+        let pool = self.pool(pool_id)?;
+
+        if pool.unbonding_period > 0 {
+            return Err(Error::UnbondingRequired);
+        }
+
+        if pool.vault_shares_for(amount) == 0 {
+            return Err(Error::ZeroAmount);
+        }
+
+        let token_address = pool.staking_token_address;
+
+        self.check_stake_reserve(pool_id, amount)?;
+        self.transfer_tokens(&token_address, &msg::source(), &exec::program_id(), amount)
+            .await?;
+
+        // The reward produced during the transfer belongs to the current holders
+        self.harvest(pool_id)?;
+
+        let pool = self.pool_mut(pool_id)?;
+        let shares = pool.vault_shares_for(amount);
+
+        pool.deposit(&vault_key(), amount, BASIS_POINTS, None);
+        pool.mint_vault_shares(&msg::source(), shares, exec::block_timestamp());
+
+        Ok(StakingEvent::VaultDeposited { amount, shares })
+    }
+
+    /// Redeems the vault's shares for the tokens at the current exchange rate
+    /// The early withdrawal penalty is charged on the holder's average deposit time
+    /// The shares are burnt before the transfer and restored if it fails
+    /// Arguments:
+    /// `shares`: the number of shares
+    async fn vault_withdraw(
+        &mut self,
+        pool_id: PoolId,
+        shares: u128,
+    ) -> Result<StakingEvent, Error> {
+        if shares == 0 {
+            return Err(Error::ZeroAmount);
+        }
+
+        self.harvest(pool_id)?;
+
+        let vault = vault_key();
+        let pool = self.pool_mut(pool_id)?;
+
+        if pool.unbonding_period > 0 {
+            return Err(Error::UnbondingRequired);
+        }
+
+        let owned = pool
+            .vault_shares
+            .get(&msg::source())
+            .copied()
+            .unwrap_or_default();
+
+        if owned < shares {
+            return Err(Error::InsufficentBalance);
+        }
+
+        let amount = pool.vault_assets_for(shares);
+
+        if amount == 0 {
+            return Err(Error::ZeroAmount);
+        }
+
+        pool.check_unlocked(&vault, amount)?;
+
+        let Some(staker) = pool.stakers.get(&vault) else {
+            return Err(Error::InsufficentBalance);
+        };
+        let (staked_at, since) = (staker.staked_at, staker.since);
+        let deposited_at = pool
+            .vault_deposited_at
+            .get(&msg::source())
+            .copied()
+            .unwrap_or_default();
+        let penalty = pool.calc_penalty_at(deposited_at, staker_weight(staker), amount);
+
+        pool.update_reward();
+        pool.decrease_weight(&vault, amount);
+
+        if let Some(staker) = pool.stakers.get_mut(&vault) {
+            subtract_balance(staker, amount);
+        }
+
+        pool.checkpoint(&vault);
+        pool.burn_vault_shares(&msg::source(), shares);
+
+        let token_address = pool.staking_token_address;
+
+        if amount > penalty {
+            if let Err(error) = self
+                .transfer_tokens(
+                    &token_address,
+                    &exec::program_id(),
+                    &msg::source(),
+                    amount - penalty,
+                )
+                .await
+            {
+                let pool = self.pool_mut(pool_id)?;
+
+                pool.update_reward();
+                pool.increase_weight(&vault, amount);
+                add_balance_at(
+                    pool.stakers.entry(vault).or_default(),
+                    amount,
+                    staked_at,
+                    since,
+                );
+                pool.checkpoint(&vault);
+                pool.mint_vault_shares(&msg::source(), shares, deposited_at);
+
+                return Err(error);
+            }
+        }
+
+        self.pool_mut(pool_id)?.charge_penalty(penalty);
+
+        // If the transfer fails, the penalties are sent with the next ones
+        self.send_penalties(pool_id).await.ok();
+
+        Ok(StakingEvent::VaultWithdrawn {
+            shares,
+            amount,
+            penalty,
+        })
+    }
+
+    /// Sets the share token minted for the stake of the pool, disabling it if `None`
+    /// The token can only change while the pool has no stakers
+    /// Arguments:
+    /// `token`: the fungible token administered by the program
+    fn set_share_token(
+        &mut self,
+        pool_id: PoolId,
+        token: Option<ActorId>,
+    ) -> Result<StakingEvent, Error> {
+        self.check_role(Role::Admin)?;
+
+        let pool = self.pool_mut(pool_id)?;
+
+        if !pool.stakers.is_empty() || pool.detached_shares > 0 {
+            return Err(Error::PoolNotEmpty);
+        }
+
+        if pool.positions && token.is_some() {
+            return Err(Error::ShareMode);
+        }
+
+        pool.share_token = token;
+
+        Ok(StakingEvent::ShareTokenUpdated(token))
+    }
+
+    /// Matches the account's staked balance with its shares
+    /// The stake of the shares moved away is detached from the account until
+    /// their new holders sync, and the rewards it earned so far stay with the account
+    /// Arguments:
+    /// `account`: the synced account
+    async fn sync_shares(
+        &mut self,
+        pool_id: PoolId,
+        account: ActorId,
+    ) -> Result<StakingEvent, Error> {
+        let token = self.pool(pool_id)?.share_token.ok_or(Error::NoShareToken)?;
+
+        // The account's own actions wait for the sync to complete,
+        // and the account can release them by repeating the sync itself
+        let foreign = account != msg::source();
+        let id = self.current_tid;
+
+        if foreign {
+            if self.transactions.contains_key(&account) {
+                return Err(Error::PreviousTxMustBeCompleted);
+            }
+
+            self.current_tid = self.current_tid.saturating_add(1);
+            self.transactions.insert(
+                account,
+                Transaction {
+                    id,
+                    action: StakingAction::SyncShares {
+                        pool: pool_id,
+                        account,
+                    },
+                },
+            );
+        }
 
-        if reward == 0 {
-            return Err(Error::ZeroReward);
+        let result = self.token_balance(&token, &account).await;
+
+        // The account may have released the sync and started another action meanwhile
+        if foreign && self.transactions.get(&account).map(|tx| tx.id) == Some(id) {
+            self.transactions.remove(&account);
         }
 
-        let token_address = self.reward_token_address;
+        let shares = result?;
+        let pool = self.pool(pool_id)?;
+        let balance = pool
+            .stakers
+            .get(&account)
+            .map_or(0, |staker| staker.balance);
+        let attached = shares.saturating_sub(balance).min(pool.detached_shares);
 
-        self.transfer_tokens(&token_address, &exec::program_id(), &msg::source(), reward)
-            .await?;
+        // The attached stake earns the fixed-APR interest like a new one
+        if attached > 0 {
+            self.check_stake_reserve(pool_id, attached)?;
+        }
 
-        self.stakers
-            .entry(msg::source())
-            .and_modify(|stake| stake.distributed = stake.distributed.saturating_add(reward));
+        let pool = self.pool_mut(pool_id)?;
 
-        Ok(StakingEvent::Reward(reward))
-    }
+        pool.update_reward();
 
-    /// Withdraws the staked the tokens
-    /// Arguments:
-    /// `amount`: the number of withdrawn tokens
-    async fn withdraw(&mut self, amount: u128) -> Result<StakingEvent, Error> {
-        if amount == 0 {
-            return Err(Error::ZeroAmount);
-        }
+        if shares < balance {
+            let detached = balance - shares;
 
-        self.update_reward();
-        let amount_per_token = self.get_max_reward(amount);
+            pool.decrease_weight(&account, detached);
 
-        match self.stakers.get(&msg::source()) {
-            Some(staker) => {
-                if staker.balance < amount {
-                    return Err(Error::InsufficentBalance);
-                }
+            if let Some(staker) = pool.stakers.get_mut(&account) {
+                subtract_balance(staker, detached);
             }
-            None => return Err(Error::StakerNotFound),
-        };
 
-        let token_address = self.staking_token_address;
-        self.transfer_tokens(&token_address, &exec::program_id(), &msg::source(), amount)
-            .await?;
+            pool.checkpoint(&account);
+            pool.detached_shares = pool.detached_shares.saturating_add(detached);
+        } else if attached > 0 {
+            pool.increase_weight(&account, attached);
+            add_balance(pool.stakers.entry(account).or_default(), attached);
+            pool.checkpoint(&account);
+            pool.detached_shares -= attached;
+        }
 
-        let staker = self
+        let balance = pool
             .stakers
-            .get_mut(&msg::source())
-            .ok_or(Error::StakerNotFound)?;
+            .get(&account)
+            .map_or(0, |staker| staker.balance);
+
+        Ok(StakingEvent::SharesSynced { account, balance })
+    }
 
-        staker.reward_allowed = staker.reward_allowed.saturating_add(amount_per_token);
-        staker.balance = staker.balance.saturating_sub(amount);
-        self.total_staked = self.total_staked.saturating_sub(amount);
+    /// Queries the account's balance of the fungible token
+    /// Arguments:
+    /// `token_address`: the token program
+    /// `account`: the account
+    async fn token_balance(
+        &self,
+        token_address: &ActorId,
+        account: &ActorId,
+    ) -> Result<u128, Error> {
+        let reply =
+            msg::send_for_reply_as(*token_address, FTokenAction::GetBalance(*account), 0, 0)?
+                .await?;
 
-        Ok(StakingEvent::Withdrawn(amount))
+        match reply {
+            FTokenEvent::Balance(balance) => Ok(balance),
+            _ => Err(Error::TransferTokens),
+        }
     }
 }
 
@@ -233,6 +3019,7 @@ async fn main() {
 
     let action: StakingAction = msg::load().expect("Could not load Action");
     let msg_source = msg::source();
+    let value = msg::value();
 
     let _reply: Result<StakingEvent, Error> = Err(Error::PreviousTxMustBeCompleted);
     let _transaction_id = if let Some(Transaction {
@@ -241,11 +3028,24 @@ async fn main() {
     }) = staking.transactions.get(&msg_source)
     {
         if action != *pend_action {
-            reply(_reply).expect("Failed to encode or reply with `Result<StakingEvent, Error>`");
+            reply(_reply, value)
+                .expect("Failed to encode or reply with `Result<StakingEvent, Error>`");
             return;
         }
         *id
     } else {
+        if let Err(error) = staking.check_paused(&action) {
+            reply(Err::<StakingEvent, Error>(error), value)
+                .expect("Failed to encode or reply with `Result<StakingEvent, Error>`");
+            return;
+        }
+
+        if value > 0 && !accepts_value(&action) {
+            reply(Err::<StakingEvent, Error>(Error::InvalidValue), value)
+                .expect("Failed to encode or reply with `Result<StakingEvent, Error>`");
+            return;
+        }
+
         let transaction_id = staking.current_tid;
         staking.current_tid = staking.current_tid.saturating_add(1);
         staking.transactions.insert(
@@ -258,28 +3058,282 @@ async fn main() {
         transaction_id
     };
     let result = match action {
-        StakingAction::Stake(amount) => {
-            let result = staking.stake(amount).await;
+        StakingAction::CreatePool(config) => {
+            let result = staking.create_pool(config);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::Stake { pool, amount } => {
+            let result = staking.stake(pool, msg_source, amount, None, None).await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::StakeLocked { pool, amount, term } => {
+            let result = staking
+                .stake(pool, msg_source, amount, Some(term), None)
+                .await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::StakeFor {
+            pool,
+            beneficiary,
+            amount,
+            locked_until,
+        } => {
+            let result = staking
+                .stake_for(pool, beneficiary, amount, locked_until)
+                .await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::Compound { pool } => {
+            let result = staking.compound(pool, msg_source).await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::Withdraw { pool, amount } => {
+            let result = staking.withdraw(pool, msg_source, msg_source, amount).await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::SetUnbondingPeriod { pool, period } => {
+            let result = staking.set_unbonding_period(pool, period);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::RequestUnstake { pool, amount } => {
+            let result = staking.request_unstake(pool, msg_source, amount).await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::ClaimUnbonded { pool } => {
+            let result = staking.claim_unbonded(pool, msg_source, msg_source).await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::VaultDeposit { pool, amount } => {
+            let result = staking.vault_deposit(pool, amount).await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::VaultWithdraw { pool, shares } => {
+            let result = staking.vault_withdraw(pool, shares).await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::SetShareToken { pool, token } => {
+            let result = staking.set_share_token(pool, token);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::SyncShares { pool, account } => {
+            let result = staking.sync_shares(pool, account).await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::TransferStake { pool, to, amount } => {
+            let result = staking.transfer_stake(pool, to, amount);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::Rebond { pool, index } => {
+            let result = staking.rebond(pool, msg_source, index).await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::EmergencyWithdraw { pool } => {
+            let result = staking
+                .emergency_withdraw(pool, msg_source, msg_source)
+                .await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::SetTiers { pool, tiers } => {
+            let result = staking.set_tiers(pool, tiers);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::SetLoyalty { pool, config } => {
+            let result = staking.set_loyalty(pool, config);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::SetPositionMode { pool, enabled } => {
+            let result = staking.set_position_mode(pool, enabled);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::Position { id, action } => {
+            let result = staking.position_action(id, action).await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::TransferPosition { id, to } => {
+            let result = staking.transfer_position(id, to).await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::SetForfeitDestination { pool, destination } => {
+            let result = staking.set_forfeit_destination(pool, destination);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::ClaimForfeited { pool, recipient } => {
+            let result = staking.claim_forfeited(pool, recipient).await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::SettleOwed { pool, account } => {
+            let result = staking.settle_owed(pool, account).await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::Pause(mask) => {
+            let result = staking.pause(mask);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::Unpause(mask) => {
+            let result = staking.unpause(mask);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::GrantRole { actor, role } => {
+            let result = staking.grant_role(actor, role);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::RevokeRole { actor, role } => {
+            let result = staking.revoke_role(actor, role);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::TransferOwnership(new_owner) => {
+            let result = staking.transfer_ownership(new_owner);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::AcceptOwnership => {
+            let result = staking.accept_ownership();
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::RenounceOwnership => {
+            let result = staking.renounce_ownership();
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::SetPenalty { pool, config } => {
+            let result = staking.set_penalty(pool, config);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::UpdateStaking { pool, config } => {
+            let result = staking.update_staking(pool, config);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::NotifyRewardAmount {
+            pool,
+            token,
+            amount,
+        } => {
+            let result = staking.notify_reward_amount(pool, token, amount);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::SetFixedApr {
+            pool,
+            token,
+            kind,
+            rate,
+            coverage,
+        } => {
+            let result = staking.set_fixed_apr(pool, token, kind, rate, coverage);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::AddEpoch {
+            pool,
+            token,
+            kind,
+            epoch,
+        } => {
+            let result = staking.add_epoch(pool, token, kind, epoch);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::CancelEpoch { pool, token, index } => {
+            let result = staking.cancel_epoch(pool, token, index);
             staking.transactions.remove(&msg_source);
             result
         }
-        StakingAction::Withdraw(amount) => {
-            let result = staking.withdraw(amount).await;
+        StakingAction::FundRewards {
+            token,
+            kind,
+            amount,
+        } => {
+            let result = staking.fund_rewards(token, kind, amount).await;
             staking.transactions.remove(&msg_source);
             result
         }
-        StakingAction::UpdateStaking(config) => {
-            let result = staking.update_staking(config);
+        StakingAction::SetLockTerms { pool, terms } => {
+            let result = staking.set_lock_terms(pool, terms);
             staking.transactions.remove(&msg_source);
             result
         }
-        StakingAction::GetReward => {
-            let result = staking.send_reward().await;
+        StakingAction::GetReward { pool, token } => {
+            let recipient = staking.reward_recipient(&msg_source);
+            let result = staking
+                .send_reward(pool, msg_source, recipient, token)
+                .await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::GetRewardTo {
+            pool,
+            token,
+            recipient,
+        } => {
+            let result = if recipient.is_zero() {
+                Err(Error::ZeroAddress)
+            } else {
+                staking
+                    .send_reward(pool, msg_source, recipient, token)
+                    .await
+            };
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::ClaimFor {
+            pool,
+            staker,
+            token,
+        } => {
+            let result = staking.claim_for(pool, staker, token).await;
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::ApproveClaimer(operator) => {
+            let result = staking.approve_claimer(operator);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::RevokeClaimer(operator) => {
+            let result = staking.revoke_claimer(operator);
+            staking.transactions.remove(&msg_source);
+            result
+        }
+        StakingAction::SetRewardRecipient(recipient) => {
+            let result = staking.set_reward_recipient(recipient);
             staking.transactions.remove(&msg_source);
             result
         }
     };
-    reply(result).expect("Failed to encode or reply with `Result<StakingEvent, Error>`");
+    // The attached value is only kept by the completed native deposits
+    let refund = if result.is_err() { value } else { 0 };
+    reply(result, refund).expect("Failed to encode or reply with `Result<StakingEvent, Error>`");
 }
 
 #[no_mangle]
@@ -291,10 +3345,10 @@ extern "C" fn init() {
         ..Default::default()
     };
 
-    let result = staking.update_staking(config);
+    let result = check_config(&config).map(|_| staking.add_pool(config));
     let is_err = result.is_err();
 
-    reply(result).expect("Failed to encode or reply with `Result<(), Error>` from `init()`");
+    reply(result, 0).expect("Failed to encode or reply with `Result<(), Error>` from `init()`");
 
     if is_err {
         exec::exit(ActorId::zero());
@@ -306,39 +3360,449 @@ extern "C" fn init() {
 fn common_state() -> <StakingMetadata as Metadata>::State {
     let state = static_mut_state();
 
+    let liabilities = state
+        .pools
+        .values()
+        .flat_map(|pool| pool.rewards.iter())
+        .filter(|(_token, reward)| reward.apr > 0 || reward.apr_accrued > 0)
+        .map(|(token, _reward)| (*token, state.liability(token)))
+        .collect();
+
     let Staking {
         owner,
-        staking_token_address,
-        reward_token_address,
-        tokens_per_stake,
-        total_staked,
-        distribution_time,
-        produced_time,
-        reward_total,
-        all_produced,
-        reward_produced,
-        stakers,
+        pending_owner,
+        roles,
+        funds,
+        claimers,
+        reward_recipients,
+        positions,
+        next_position_id,
+        token_kinds,
+        pools,
+        next_pool_id,
         transactions,
         current_tid,
+        paused,
     } = state.clone();
 
-    let stakers = stakers.iter().map(|(k, v)| (*k, v.clone())).collect();
+    let pools = pools
+        .into_iter()
+        .map(|(id, pool)| (id, pool.into()))
+        .collect();
 
     IoStaking {
         owner,
-        staking_token_address,
-        reward_token_address,
-        tokens_per_stake,
-        total_staked,
-        distribution_time,
-        produced_time,
-        reward_total,
-        all_produced,
-        reward_produced,
-        stakers,
+        pending_owner,
+        roles,
+        funds,
+        liabilities,
+        claimers,
+        reward_recipients,
+        positions,
+        next_position_id,
+        token_kinds,
+        pools,
+        next_pool_id,
         transactions,
         current_tid,
+        paused,
+    }
+}
+
+impl From<Pool> for IoPool {
+    fn from(pool: Pool) -> Self {
+        let total_assets = pool.vault_assets();
+        let price_per_share = pool.price_per_share();
+
+        let Pool {
+            staking_token_address,
+            total_staked,
+            rewards,
+            stakers,
+            lock_terms,
+            lock_expirations,
+            unbonding_period,
+            penalty,
+            unpaid_penalties,
+            penalty_per_stake,
+            forfeit_destination,
+            forfeited,
+            forfeited_penalties,
+            tiers,
+            loyalty,
+            positions,
+            share_token,
+            detached_shares,
+            owed_refunds,
+            owed_shares,
+            vault_shares,
+            vault_deposited_at,
+            vault_total_shares,
+        } = pool;
+
+        let rewards = rewards
+            .into_iter()
+            .map(|(token, reward)| (token, reward.into()))
+            .collect();
+        let stakers = stakers.into_iter().collect();
+
+        Self {
+            staking_token_address,
+            total_staked,
+            rewards,
+            stakers,
+            lock_terms,
+            lock_expirations,
+            unbonding_period,
+            penalty,
+            unpaid_penalties,
+            penalty_per_stake,
+            forfeit_destination,
+            forfeited,
+            forfeited_penalties,
+            tiers,
+            loyalty,
+            positions,
+            share_token,
+            detached_shares,
+            owed_refunds,
+            owed_shares,
+            vault: IoVault {
+                total_shares: vault_total_shares,
+                total_assets,
+                price_per_share,
+                shares: vault_shares.into_iter().collect(),
+                deposited_at: vault_deposited_at.into_iter().collect(),
+            },
+        }
+    }
+}
+
+impl From<Reward> for IoReward {
+    fn from(reward: Reward) -> Self {
+        let Reward {
+            tokens_per_stake,
+            distribution_time,
+            produced_time,
+            reward_total,
+            all_produced,
+            reward_produced,
+            epochs,
+            curve,
+            apr,
+            apr_coverage,
+            apr_accrued,
+            updated_at,
+        } = reward;
+
+        Self {
+            tokens_per_stake,
+            distribution_time,
+            produced_time,
+            reward_total,
+            all_produced,
+            reward_produced,
+            epochs,
+            curve,
+            apr,
+            apr_coverage,
+            apr_accrued,
+            updated_at,
+        }
+    }
+}
+
+/// Calculates the part of the reward produced in the elapsed time according to the curve
+/// The curve is scaled so that the whole reward is produced by the end of the duration
+/// Arguments:
+/// `curve`: the emission curve
+/// `reward`: the reward produced within the duration
+/// `elapsed`: the time since the start of the distribution
+/// `duration`: the distribution time
+fn curve_produced(curve: &EmissionCurve, reward: u128, elapsed: u64, duration: u64) -> u128 {
+    let elapsed = elapsed.min(duration);
+
+    let (produced, total) = match curve {
+        EmissionCurve::Linear => (elapsed.into(), duration.into()),
+        EmissionCurve::ExponentialDecay { half_life } => (
+            Q63 - exp2_neg(elapsed, *half_life),
+            Q63 - exp2_neg(duration, *half_life),
+        ),
+        EmissionCurve::Halving { period } => (
+            halving_weight(elapsed, *period),
+            halving_weight(duration, *period),
+        ),
+        EmissionCurve::Piecewise(points) => (
+            piecewise_weight(points, elapsed),
+            piecewise_weight(points, duration),
+        ),
+    };
+
+    if total == 0 {
+        return mul_div(reward, elapsed.into(), duration.into());
+    }
+
+    mul_div(reward, produced, total)
+}
+
+/// Calculates 2^(-elapsed/half_life) in the Q63 fixed-point format
+/// Arguments:
+/// `elapsed`: the time since the start of the distribution
+/// `half_life`: the time in which the emission rate halves
+fn exp2_neg(elapsed: u64, half_life: u64) -> u128 {
+    let half_life = u128::from(half_life);
+    let halvings = u128::from(elapsed) / half_life;
+
+    if halvings >= 63 {
+        return 0;
+    }
+
+    let mut result = Q63 >> halvings;
+    let mut rest = u128::from(elapsed) % half_life;
+
+    // The fractional part of the exponent is applied bit by bit
+    for factor in EXP2_NEG_FRACTIONS {
+        rest *= 2;
+
+        if rest >= half_life {
+            rest -= half_life;
+            result = (result * factor) >> 63;
+        }
+    }
+
+    result
+}
+
+/// Calculates the integral of the emission rate that halves every period
+/// Arguments:
+/// `elapsed`: the time since the start of the distribution
+/// `period`: the time after which the emission rate halves
+fn halving_weight(elapsed: u64, period: u64) -> u128 {
+    let halvings = elapsed / period;
+    let rate = u32::try_from(halvings)
+        .ok()
+        .and_then(|halvings| HALVING_WEIGHT.checked_shr(halvings))
+        .unwrap_or_default();
+
+    // The full periods produce 1 + 1/2 + ... + 1/2^(halvings - 1) of the first one
+    u128::from(period) * (2 * HALVING_WEIGHT - 2 * rate)
+        + u128::from(elapsed - halvings * period) * rate
+}
+
+/// Calculates the integral of the piecewise constant emission rate
+/// Arguments:
+/// `points`: the offsets from the start of the distribution and the rates starting at them
+/// `elapsed`: the time since the start of the distribution
+fn piecewise_weight(points: &[(u64, u128)], elapsed: u64) -> u128 {
+    points
+        .iter()
+        .enumerate()
+        .fold(0, |weight: u128, (i, (from, rate))| {
+            let to = points.get(i + 1).map_or(u64::MAX, |(to, _rate)| *to);
+            let covered = elapsed.min(to).saturating_sub(*from);
+
+            weight.saturating_add(rate.saturating_mul(covered.into()))
+        })
+}
+
+/// Calculates `a * b / c` without overflowing on the intermediate product if possible
+fn mul_div(a: u128, b: u128, c: u128) -> u128 {
+    match a.checked_mul(b) {
+        Some(product) => product / c,
+        None => (a / c)
+            .saturating_mul(b)
+            .saturating_add((a % c).saturating_mul(b) / c),
+    }
+}
+
+/// Calculates the interest on the amount at the annual rate
+/// Arguments:
+/// `amount`: the number of tokens
+/// `rate`: the annual rate in basis points
+/// `duration`: the time for which the interest is calculated
+fn interest(amount: u128, rate: u32, duration: u64) -> u128 {
+    mul_div(
+        amount.saturating_mul(rate.into()),
+        duration.into(),
+        u128::from(BASIS_POINTS) * u128::from(YEAR),
+    )
+}
+
+/// Calculates the reward produced by the epoch by the given time
+/// The reward is produced evenly from the start of the epoch to its end
+/// Arguments:
+/// `epoch`: the epoch
+/// `time`: the timestamp up to which the reward is calculated
+fn epoch_produced(epoch: &Epoch, time: u64) -> u128 {
+    if time <= epoch.start {
+        return 0;
+    }
+
+    let elapsed_time = time.min(epoch.end) - epoch.start;
+
+    mul_div(
+        epoch.amount,
+        elapsed_time.into(),
+        (epoch.end - epoch.start).into(),
+    )
+}
+
+/// Checks that the account can be credited with the stake
+/// The positions and the vault only hold the stake deposited through them
+/// Arguments:
+/// `account`: the credited account
+fn check_recipient(account: &ActorId) -> Result<(), Error> {
+    if account.is_zero() {
+        return Err(Error::ZeroAddress);
+    }
+
+    if is_reserved(account) {
+        return Err(Error::ReservedAddress);
+    }
+
+    Ok(())
+}
+
+/// Checks that the reward schedule distributes something
+/// Arguments:
+/// `config`: the reward schedule
+fn check_config(config: &InitStaking) -> Result<(), Error> {
+    if config.reward_total == 0 {
+        return Err(Error::ZeroReward);
+    }
+
+    if config.distribution_time == 0 {
+        return Err(Error::ZeroTime);
+    }
+
+    let tokens = [
+        (config.staking_token_address, config.staking_token_kind),
+        (config.reward_token_address, config.reward_token_kind),
+    ];
+
+    if tokens
+        .iter()
+        .any(|(token, kind)| token.is_zero() != (*kind == TokenKind::Native))
+    {
+        return Err(Error::InvalidTokenKind);
+    }
+
+    let is_valid_curve = match &config.curve {
+        EmissionCurve::Linear => true,
+        EmissionCurve::ExponentialDecay { half_life } => *half_life > 0,
+        EmissionCurve::Halving { period } => *period > 0,
+        EmissionCurve::Piecewise(points) => {
+            points.windows(2).all(|pair| pair[0].0 < pair[1].0)
+                && piecewise_weight(points, config.distribution_time) > 0
+        }
+    };
+
+    if !is_valid_curve {
+        return Err(Error::InvalidCurve);
+    }
+
+    Ok(())
+}
+
+/// Calculates the staker's weight in the reward accumulator
+/// The effective balance is scaled by the staker's tier and loyalty multipliers
+/// Arguments:
+/// `staker`: the staker
+fn staker_weight(staker: &Staker) -> u128 {
+    [staker.tier_weight, staker.loyalty_weight]
+        .into_iter()
+        .filter(|weight| *weight > 0)
+        .fold(staker.effective_balance, |weight, multiplier| {
+            weight.saturating_mul(multiplier.into()) / BASIS_POINTS as u128
+        })
+}
+
+/// Adds the penalties to the payouts of the rewards in the staking token
+/// Arguments:
+/// `rewards`: the rewards and their tokens
+/// `staking_token`: the staking token
+/// `penalties`: the penalties paid along with the rewards
+fn with_penalties_paid(
+    rewards: &[(ActorId, u128)],
+    staking_token: ActorId,
+    penalties: u128,
+) -> Vec<(ActorId, u128)> {
+    let mut payouts = rewards.to_vec();
+
+    if penalties > 0 {
+        match payouts
+            .iter_mut()
+            .find(|(token, _)| *token == staking_token)
+        {
+            Some((_, payout)) => *payout = payout.saturating_add(penalties),
+            None => payouts.push((staking_token, penalties)),
+        }
+    }
+
+    payouts
+}
+
+/// Calculates the reward that the weight would have received from the accumulator since the start
+/// Arguments:
+/// `weight`: the staker's weight
+/// `per_stake`: the accumulated reward per unit of weight
+fn accumulated(weight: u128, per_stake: u128) -> u128 {
+    (weight * per_stake) / DECIMALS_FACTOR
+}
+
+/// Adds the tokens to the staker's balance
+/// The deposit time becomes the average of the balance's deposit times
+/// Arguments:
+/// `staker`: the staker
+/// `amount`: the number of tokens
+fn add_balance(staker: &mut Staker, amount: u128) {
+    let now = exec::block_timestamp();
+
+    add_balance_at(staker, amount, now, now);
+}
+
+/// Adds the tokens deposited and continuously staked since the given times
+/// to the staker's balance
+/// Arguments:
+/// `staker`: the staker
+/// `amount`: the number of tokens
+/// `staked_at`: the deposit time of the tokens
+/// `since`: the start of the continuous staking of the tokens
+fn add_balance_at(staker: &mut Staker, amount: u128, staked_at: u64, since: u64) {
+    let balance = staker.balance.saturating_add(amount);
+
+    if balance > 0 {
+        let average = |current: u64, added: u64| {
+            let sum = u128::from(current).saturating_mul(staker.balance)
+                + u128::from(added).saturating_mul(amount);
+
+            (sum / balance) as u64
+        };
+
+        staker.staked_at = average(staker.staked_at, staked_at);
+        staker.since = average(staker.since, since);
+    }
+
+    staker.balance = balance;
+}
+
+/// Subtracts the tokens from the staker's balance
+/// The continuous staking time is reduced in proportion to the subtracted part
+/// Arguments:
+/// `staker`: the staker
+/// `amount`: the number of tokens
+fn subtract_balance(staker: &mut Staker, amount: u128) {
+    let balance = staker.balance.saturating_sub(amount);
+
+    if staker.balance > 0 {
+        let now = exec::block_timestamp();
+        let held =
+            u128::from(now.saturating_sub(staker.since)).saturating_mul(balance) / staker.balance;
+
+        staker.since = now.saturating_sub(held as u64);
     }
+
+    staker.balance = balance;
 }
 
 fn static_mut_state() -> &'static mut Staking {
@@ -347,10 +3811,24 @@ fn static_mut_state() -> &'static mut Staking {
 
 #[no_mangle]
 extern "C" fn state() {
-    reply(common_state())
+    reply(common_state(), 0)
         .expect("Failed to encode or reply with `<AppMetadata as Metadata>::State` from `state()`");
 }
 
-fn reply(payload: impl Encode) -> GstdResult<MessageId> {
-    msg::reply(payload, 0)
+fn reply(payload: impl Encode, value: u128) -> GstdResult<MessageId> {
+    msg::reply(payload, value)
+}
+
+/// Checks that the action can take the attached value for a native token
+/// Arguments:
+/// `action`: the incoming action
+fn accepts_value(action: &StakingAction) -> bool {
+    matches!(
+        action,
+        StakingAction::Stake { .. }
+            | StakingAction::StakeLocked { .. }
+            | StakingAction::StakeFor { .. }
+            | StakingAction::VaultDeposit { .. }
+            | StakingAction::FundRewards { .. }
+    )
 }
@@ -2,6 +2,8 @@
 
 #[cfg(not(feature = "binary-vendor"))]
 mod contract;
+#[cfg(not(feature = "binary-vendor"))]
+mod token;
 
 #[cfg(feature = "binary-vendor")]
 include!(concat!(env!("OUT_DIR"), "/wasm_binary.rs"));
@@ -0,0 +1,141 @@
+use core::{future::Future, pin::Pin};
+use ft_io::FTAction;
+use ft_main_io::*;
+use gstd::{exec, msg, prelude::*, ActorId};
+use staking_io::*;
+
+/// The transfer made by a token adapter
+pub type Transfer = Pin<Box<dyn Future<Output = Result<(), Error>>>>;
+
+/// Moves the tokens of one token standard
+pub trait TokenAdapter {
+    /// Transfers `amount` tokens from `from` account to `to` account
+    /// Arguments:
+    /// `transaction_id`: the id of the transfer for the standards deduplicating them
+    /// `from`: sender account
+    /// `to`: recipient account
+    /// `amount`: amount of tokens
+    fn transfer(
+        &self,
+        transaction_id: TransactionId,
+        from: ActorId,
+        to: ActorId,
+        amount: u128,
+    ) -> Transfer;
+}
+
+/// Returns the adapter of the token
+/// Arguments:
+/// `kind`: the standard of the token
+/// `token`: the token address
+pub fn adapter(kind: TokenKind, token: ActorId) -> Box<dyn TokenAdapter> {
+    match kind {
+        TokenKind::Sharded => Box::new(ShardedToken(token)),
+        TokenKind::Fungible => Box::new(FungibleToken(token)),
+        TokenKind::Native => Box::new(NativeToken),
+    }
+}
+
+/// The sharded fungible token
+pub struct ShardedToken(pub ActorId);
+
+impl ShardedToken {
+    /// Sends the message to the token program
+    /// Arguments:
+    /// `transaction_id`: the id the token deduplicates the message by
+    /// `payload`: the message
+    pub async fn send(
+        &self,
+        transaction_id: TransactionId,
+        payload: LogicAction,
+    ) -> Result<(), Error> {
+        let payload = FTokenAction::Message {
+            transaction_id,
+            payload,
+        };
+
+        let result = msg::send_for_reply_as(self.0, payload, 0, 0)?.await?;
+
+        if let FTokenEvent::Err = result {
+            Err(Error::TransferTokens)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl TokenAdapter for ShardedToken {
+    fn transfer(
+        &self,
+        transaction_id: TransactionId,
+        from: ActorId,
+        to: ActorId,
+        amount: u128,
+    ) -> Transfer {
+        let token = Self(self.0);
+
+        Box::pin(async move {
+            let payload = LogicAction::Transfer {
+                sender: from,
+                recipient: to,
+                amount,
+            };
+
+            token.send(transaction_id, payload).await
+        })
+    }
+}
+
+/// The gear fungible token, which isn't sharded
+/// It panics on a failed transfer, so any reply but the error one means the success
+pub struct FungibleToken(pub ActorId);
+
+impl TokenAdapter for FungibleToken {
+    fn transfer(
+        &self,
+        _transaction_id: TransactionId,
+        from: ActorId,
+        to: ActorId,
+        amount: u128,
+    ) -> Transfer {
+        let token = self.0;
+
+        Box::pin(async move {
+            let payload = FTAction::Transfer { from, to, amount };
+
+            msg::send_for_reply(token, payload, 0, 0)?
+                .await
+                .map_err(|_| Error::TransferTokens)?;
+
+            Ok(())
+        })
+    }
+}
+
+/// The native value, addressed by the zero address
+pub struct NativeToken;
+
+impl TokenAdapter for NativeToken {
+    fn transfer(
+        &self,
+        _transaction_id: TransactionId,
+        _from: ActorId,
+        to: ActorId,
+        amount: u128,
+    ) -> Transfer {
+        Box::pin(async move {
+            // The incoming value is attached to the message
+            if to == exec::program_id() {
+                return if msg::value() == amount {
+                    Ok(())
+                } else {
+                    Err(Error::InvalidValue)
+                };
+            }
+
+            msg::send(to, (), amount).map_err(|_| Error::TransferTokens)?;
+
+            Ok(())
+        })
+    }
+}
@@ -2,21 +2,79 @@
 
 use gmeta::{metawasm, Metadata};
 use gstd::{prelude::*, ActorId};
-use staking_io::{Staker, StakingMetadata};
+use staking_io::{
+    position_key, Epoch, IoPool, IoReward, PoolId, Position, PositionId, Role, Staker,
+    StakingMetadata, UnbondingChunk,
+};
 
 #[metawasm]
 pub mod metafns {
     pub type State = <StakingMetadata as Metadata>::State;
 
-    pub fn get_stakers(state: State) -> Vec<(ActorId, Staker)> {
-        state.stakers
+    pub fn get_pools(state: State) -> Vec<(PoolId, IoPool)> {
+        state.pools
     }
 
-    pub fn get_staker(state: State, address: ActorId) -> Option<Staker> {
+    pub fn get_pool(state: State, pool_id: PoolId) -> Option<IoPool> {
         state
-            .stakers
-            .iter()
+            .pools
+            .into_iter()
+            .find(|(id, _pool)| pool_id.eq(id))
+            .map(|(_, pool)| pool)
+    }
+
+    pub fn get_rewards(state: State, pool_id: PoolId) -> Vec<(ActorId, IoReward)> {
+        get_pool(state, pool_id)
+            .map(|pool| pool.rewards)
+            .unwrap_or_default()
+    }
+
+    pub fn get_schedule(state: State, pool_id: PoolId, token: ActorId) -> Vec<Epoch> {
+        get_rewards(state, pool_id)
+            .into_iter()
+            .find(|(id, _reward)| token.eq(id))
+            .map(|(_, reward)| reward.epochs)
+            .unwrap_or_default()
+    }
+
+    pub fn get_stakers(state: State, pool_id: PoolId) -> Vec<(ActorId, Staker)> {
+        get_pool(state, pool_id)
+            .map(|pool| pool.stakers)
+            .unwrap_or_default()
+    }
+
+    pub fn get_staker(state: State, pool_id: PoolId, address: ActorId) -> Option<Staker> {
+        get_stakers(state, pool_id)
+            .into_iter()
             .find(|(id, _staker)| address.eq(id))
-            .map(|(_, staker)| staker.clone())
+            .map(|(_, staker)| staker)
+    }
+
+    pub fn get_unbonding(state: State, pool_id: PoolId, address: ActorId) -> Vec<UnbondingChunk> {
+        get_staker(state, pool_id, address)
+            .map(|staker| staker.unbonding)
+            .unwrap_or_default()
+    }
+
+    pub fn get_positions(state: State, owner: ActorId) -> Vec<(PositionId, Position)> {
+        state
+            .positions
+            .into_iter()
+            .filter(|(_id, position)| owner.eq(&position.owner))
+            .collect()
+    }
+
+    pub fn get_position_staker(state: State, id: PositionId) -> Option<Staker> {
+        let pool_id = state.positions.get(&id)?.pool;
+
+        get_staker(state, pool_id, position_key(id))
+    }
+
+    pub fn has_role(state: State, actor: ActorId, role: Role) -> bool {
+        actor == state.owner
+            || state
+                .roles
+                .get(&actor)
+                .map_or(false, |roles| roles.contains(&role))
     }
 }
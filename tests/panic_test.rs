@@ -3,7 +3,7 @@ use gtest::{Program, System};
 use staking_io::*;
 
 mod utils;
-use utils::{FungibleToken, PROGRAMS};
+use utils::{FungibleToken, LegacyFungibleToken, PROGRAMS};
 
 fn init_staking(sys: &System) {
     let staking = Program::current(sys);
@@ -15,10 +15,16 @@ fn init_staking(sys: &System) {
             reward_token_address: PROGRAMS[2].into(),
             distribution_time: 10000,
             reward_total: 1000,
+            curve: EmissionCurve::Linear,
+            staking_token_kind: TokenKind::Sharded,
+            reward_token_kind: TokenKind::Sharded,
         },
     );
 
-    assert!(res.contains(&(4, Ok::<StakingEvent, Error>(StakingEvent::Updated).encode())));
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PoolCreated(0)).encode()
+    )));
 }
 
 fn init_staking_token(sys: &System) -> FungibleToken {
@@ -57,7 +63,7 @@ fn stake() {
     sys.init_logger();
     let staking = sys.get_program(1);
 
-    let res = staking.send(5, StakingAction::Stake(0));
+    let res = staking.send(5, StakingAction::Stake { pool: 0, amount: 0 });
     assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::ZeroAmount).encode())));
 }
 
@@ -70,34 +76,52 @@ fn update_staking() {
 
     let res = staking.send(
         5,
-        StakingAction::UpdateStaking(InitStaking {
-            staking_token_address: PROGRAMS[1].into(),
-            reward_token_address: PROGRAMS[2].into(),
-            distribution_time: 10000,
-            reward_total: 1000,
-        }),
+        StakingAction::UpdateStaking {
+            pool: 0,
+            config: InitStaking {
+                staking_token_address: PROGRAMS[1].into(),
+                reward_token_address: PROGRAMS[2].into(),
+                distribution_time: 10000,
+                reward_total: 1000,
+                curve: EmissionCurve::Linear,
+                staking_token_kind: TokenKind::Sharded,
+                reward_token_kind: TokenKind::Sharded,
+            },
+        },
     );
     assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
 
     let res = staking.send(
         4,
-        StakingAction::UpdateStaking(InitStaking {
-            staking_token_address: PROGRAMS[1].into(),
-            reward_token_address: PROGRAMS[2].into(),
-            distribution_time: 10000,
-            reward_total: 0,
-        }),
+        StakingAction::UpdateStaking {
+            pool: 0,
+            config: InitStaking {
+                staking_token_address: PROGRAMS[1].into(),
+                reward_token_address: PROGRAMS[2].into(),
+                distribution_time: 10000,
+                reward_total: 0,
+                curve: EmissionCurve::Linear,
+                staking_token_kind: TokenKind::Sharded,
+                reward_token_kind: TokenKind::Sharded,
+            },
+        },
     );
     assert!(res.contains(&(4, Err::<StakingEvent, Error>(Error::ZeroReward).encode())));
 
     let res = staking.send(
         4,
-        StakingAction::UpdateStaking(InitStaking {
-            staking_token_address: PROGRAMS[1].into(),
-            reward_token_address: PROGRAMS[2].into(),
-            distribution_time: 0,
-            reward_total: 1000,
-        }),
+        StakingAction::UpdateStaking {
+            pool: 0,
+            config: InitStaking {
+                staking_token_address: PROGRAMS[1].into(),
+                reward_token_address: PROGRAMS[2].into(),
+                distribution_time: 0,
+                reward_total: 1000,
+                curve: EmissionCurve::Linear,
+                staking_token_kind: TokenKind::Sharded,
+                reward_token_kind: TokenKind::Sharded,
+            },
+        },
     );
     println!("{:?}", res.decoded_log::<Result<StakingEvent, Error>>());
     assert!(res.contains(&(4, Err::<StakingEvent, Error>(Error::ZeroTime).encode())));
@@ -112,7 +136,13 @@ fn send_reward() {
     sys.init_logger();
     let staking = sys.get_program(1);
 
-    let res = staking.send(5, StakingAction::GetReward);
+    let res = staking.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
 
     assert!(res.contains(&(
         5,
@@ -133,30 +163,1404 @@ fn withdraw() {
     let id: ActorId = staking.id().into_bytes().into();
     st_token.approve(5, id, 1500);
 
-    let res = staking.send(5, StakingAction::Stake(1500));
+    let res = staking.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1500,
+        },
+    );
     assert!(res.contains(&(
         5,
         Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1500)).encode()
     )));
     st_token.approve(6, id, 2000);
-    let res = staking.send(6, StakingAction::Stake(2000));
+    let res = staking.send(
+        6,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 2000,
+        },
+    );
     assert!(res.contains(&(
         6,
         Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(2000)).encode()
     )));
 
-    let res = staking.send(5, StakingAction::Withdraw(0));
+    let res = staking.send(5, StakingAction::Withdraw { pool: 0, amount: 0 });
     assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::ZeroAmount).encode())));
 
-    let res = staking.send(7, StakingAction::Withdraw(1000));
+    let res = staking.send(
+        7,
+        StakingAction::Withdraw {
+            pool: 0,
+            amount: 1000,
+        },
+    );
     assert!(res.contains(&(
         7,
         Err::<StakingEvent, Error>(Error::StakerNotFound).encode()
     )));
 
-    let res = staking.send(6, StakingAction::Withdraw(5000));
+    let res = staking.send(
+        6,
+        StakingAction::Withdraw {
+            pool: 0,
+            amount: 5000,
+        },
+    );
     assert!(res.contains(&(
         6,
         Err::<StakingEvent, Error>(Error::InsufficentBalance).encode()
     )));
+
+    let res = staking.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: Some(PROGRAMS[1].into()),
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::RewardTokenNotFound).encode()
+    )));
+}
+
+#[test]
+fn stake_locked() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(
+        5,
+        StakingAction::SetLockTerms {
+            pool: 0,
+            terms: vec![(2000, 15000)],
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let res = staking.send(
+        4,
+        StakingAction::SetLockTerms {
+            pool: 0,
+            terms: vec![(0, 15000)],
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Err::<StakingEvent, Error>(Error::InvalidLockTerm).encode()
+    )));
+
+    let res = staking.send(
+        4,
+        StakingAction::SetLockTerms {
+            pool: 0,
+            terms: vec![(2000, 5000)],
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Err::<StakingEvent, Error>(Error::InvalidLockTerm).encode()
+    )));
+
+    let id: ActorId = staking.id().into_bytes().into();
+    st_token.approve(5, id, 1000);
+
+    let res = staking.send(
+        5,
+        StakingAction::StakeLocked {
+            pool: 0,
+            amount: 1000,
+            term: 2000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::LockTermNotFound).encode()
+    )));
+}
+
+#[test]
+fn pools() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(
+        5,
+        StakingAction::CreatePool(InitStaking {
+            staking_token_address: PROGRAMS[1].into(),
+            reward_token_address: PROGRAMS[2].into(),
+            distribution_time: 10000,
+            reward_total: 1000,
+            curve: EmissionCurve::Linear,
+            staking_token_kind: TokenKind::Sharded,
+            reward_token_kind: TokenKind::Sharded,
+        }),
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let res = staking.send(
+        5,
+        StakingAction::Stake {
+            pool: 1,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::PoolNotFound).encode())));
+
+    st_token.approve(5, staking.id().into_bytes(), 1000);
+    let res = staking.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    // The staked tokens can't be switched to another token
+    for (token, kind) in [
+        (ActorId::from(10), TokenKind::Sharded),
+        (ActorId::zero(), TokenKind::Native),
+    ] {
+        let res = staking.send(
+            4,
+            StakingAction::UpdateStaking {
+                pool: 0,
+                config: InitStaking {
+                    staking_token_address: token,
+                    reward_token_address: PROGRAMS[2].into(),
+                    distribution_time: 10000,
+                    reward_total: 1000,
+                    curve: EmissionCurve::Linear,
+                    staking_token_kind: kind,
+                    reward_token_kind: TokenKind::Sharded,
+                },
+            },
+        );
+        assert!(res.contains(&(4, Err::<StakingEvent, Error>(Error::PoolNotEmpty).encode())));
+    }
+}
+
+#[test]
+fn compound() {
+    let sys = System::new();
+    init_staking(&sys);
+    init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(5, StakingAction::Compound { pool: 0 });
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::RewardTokenNotFound).encode()
+    )));
+}
+
+#[test]
+fn unbonding() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(
+        5,
+        StakingAction::SetUnbondingPeriod {
+            pool: 0,
+            period: 2000,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let res = staking.send(
+        4,
+        StakingAction::SetUnbondingPeriod {
+            pool: 0,
+            period: 2000,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::UnbondingPeriodUpdated).encode()
+    )));
+
+    let id: ActorId = staking.id().into_bytes().into();
+    st_token.approve(5, id, 1000);
+
+    let res = staking.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    let res = staking.send(
+        5,
+        StakingAction::Withdraw {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::UnbondingRequired).encode()
+    )));
+
+    let res = staking.send(
+        5,
+        StakingAction::RequestUnstake {
+            pool: 0,
+            amount: 2000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::InsufficentBalance).encode()
+    )));
+
+    let res = staking.send(5, StakingAction::Rebond { pool: 0, index: 0 });
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::UnbondingNotFound).encode()
+    )));
+}
+
+#[test]
+fn penalty() {
+    let sys = System::new();
+    init_staking(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let config = PenaltyConfig {
+        rate: 1000,
+        holding_period: 10000,
+        destination: PenaltyDestination::Stakers,
+    };
+
+    let res = staking.send(
+        5,
+        StakingAction::SetPenalty {
+            pool: 0,
+            config: Some(config.clone()),
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let res = staking.send(
+        4,
+        StakingAction::SetPenalty {
+            pool: 0,
+            config: Some(PenaltyConfig {
+                rate: BASIS_POINTS + 1,
+                ..config
+            }),
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Err::<StakingEvent, Error>(Error::InvalidPenalty).encode()
+    )));
+
+    let res = staking.send(
+        4,
+        StakingAction::SetPenalty {
+            pool: 0,
+            config: Some(PenaltyConfig {
+                holding_period: 0,
+                ..config
+            }),
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Err::<StakingEvent, Error>(Error::InvalidPenalty).encode()
+    )));
+}
+
+#[test]
+fn emergency_withdraw() {
+    let sys = System::new();
+    init_staking(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(5, StakingAction::EmergencyWithdraw { pool: 0 });
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::StakerNotFound).encode()
+    )));
+
+    let res = staking.send(
+        5,
+        StakingAction::SetForfeitDestination {
+            pool: 0,
+            destination: ForfeitDestination::Owner,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let res = staking.send(
+        5,
+        StakingAction::ClaimForfeited {
+            pool: 0,
+            recipient: 5.into(),
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let res = staking.send(
+        4,
+        StakingAction::ClaimForfeited {
+            pool: 0,
+            recipient: 4.into(),
+        },
+    );
+    assert!(res.contains(&(4, Err::<StakingEvent, Error>(Error::ZeroReward).encode())));
+
+    // The forfeited rewards only go to the owner
+    let res = staking.send(
+        4,
+        StakingAction::GrantRole {
+            actor: 5.into(),
+            role: Role::Admin,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::RoleGranted {
+            actor: 5.into(),
+            role: Role::Admin,
+        })
+        .encode()
+    )));
+
+    let res = staking.send(
+        5,
+        StakingAction::ClaimForfeited {
+            pool: 0,
+            recipient: 5.into(),
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+}
+
+#[test]
+fn pause() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(5, StakingAction::Pause(ActionMask::default()));
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let res = staking.send(5, StakingAction::Unpause(ActionMask::default()));
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let mask = ActionMask {
+        stake: true,
+        emergency_withdraw: true,
+        ..Default::default()
+    };
+    let res = staking.send(4, StakingAction::Pause(mask));
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::Paused(mask)).encode()
+    )));
+
+    st_token.approve(5, staking.id().into_bytes(), 1000);
+    let res = staking.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::Paused).encode())));
+
+    let res = staking.send(5, StakingAction::EmergencyWithdraw { pool: 0 });
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::Paused).encode())));
+}
+
+#[test]
+fn ownership() {
+    let sys = System::new();
+    init_staking(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(5, StakingAction::TransferOwnership(5.into()));
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let res = staking.send(5, StakingAction::AcceptOwnership);
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::NotPendingOwner).encode()
+    )));
+
+    let res = staking.send(4, StakingAction::TransferOwnership(5.into()));
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::OwnershipTransferStarted(5.into())).encode()
+    )));
+
+    let res = staking.send(6, StakingAction::AcceptOwnership);
+    assert!(res.contains(&(
+        6,
+        Err::<StakingEvent, Error>(Error::NotPendingOwner).encode()
+    )));
+
+    let res = staking.send(4, StakingAction::RenounceOwnership);
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::OwnershipRenounced).encode()
+    )));
+
+    // Renouncing cancels the pending transfer
+    let res = staking.send(5, StakingAction::AcceptOwnership);
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::NotPendingOwner).encode()
+    )));
+
+    let res = staking.send(4, StakingAction::Unpause(ActionMask::default()));
+    assert!(res.contains(&(4, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+}
+
+#[test]
+fn roles() {
+    let sys = System::new();
+    init_staking(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(
+        5,
+        StakingAction::GrantRole {
+            actor: 5.into(),
+            role: Role::Pauser,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let res = staking.send(
+        4,
+        StakingAction::GrantRole {
+            actor: 5.into(),
+            role: Role::Admin,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::RoleGranted {
+            actor: 5.into(),
+            role: Role::Admin,
+        })
+        .encode()
+    )));
+
+    // Only the owner manages admins
+    let res = staking.send(
+        5,
+        StakingAction::GrantRole {
+            actor: 6.into(),
+            role: Role::Admin,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    // An admin isn't a pauser
+    let res = staking.send(5, StakingAction::Pause(ActionMask::default()));
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+}
+
+#[test]
+fn fund_rewards() {
+    let sys = System::new();
+    init_staking(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(
+        4,
+        StakingAction::FundRewards {
+            token: PROGRAMS[2].into(),
+            kind: TokenKind::Sharded,
+            amount: 0,
+        },
+    );
+    assert!(res.contains(&(4, Err::<StakingEvent, Error>(Error::ZeroAmount).encode())));
+
+    // The initial pool isn't funded
+    let config = InitStaking {
+        staking_token_address: PROGRAMS[1].into(),
+        reward_token_address: PROGRAMS[2].into(),
+        distribution_time: 10000,
+        reward_total: 1000,
+        curve: EmissionCurve::Linear,
+        staking_token_kind: TokenKind::Sharded,
+        reward_token_kind: TokenKind::Sharded,
+    };
+
+    let res = staking.send(
+        4,
+        StakingAction::UpdateStaking {
+            pool: 0,
+            config: config.clone(),
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Err::<StakingEvent, Error>(Error::InsufficientFunds).encode()
+    )));
+
+    let res = staking.send(4, StakingAction::CreatePool(config));
+    assert!(res.contains(&(
+        4,
+        Err::<StakingEvent, Error>(Error::InsufficientFunds).encode()
+    )));
+}
+
+#[test]
+fn notify_reward_amount() {
+    let sys = System::new();
+    init_staking(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(
+        5,
+        StakingAction::NotifyRewardAmount {
+            pool: 0,
+            token: PROGRAMS[2].into(),
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let res = staking.send(
+        4,
+        StakingAction::NotifyRewardAmount {
+            pool: 0,
+            token: PROGRAMS[1].into(),
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Err::<StakingEvent, Error>(Error::RewardTokenNotFound).encode()
+    )));
+
+    let res = staking.send(
+        4,
+        StakingAction::NotifyRewardAmount {
+            pool: 0,
+            token: PROGRAMS[2].into(),
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Err::<StakingEvent, Error>(Error::InsufficientFunds).encode()
+    )));
+}
+
+#[test]
+fn epochs() {
+    let sys = System::new();
+    init_staking(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let epoch = Epoch {
+        start: 5000,
+        end: 10000,
+        amount: 1000,
+    };
+
+    let res = staking.send(
+        5,
+        StakingAction::AddEpoch {
+            pool: 0,
+            token: PROGRAMS[2].into(),
+            kind: TokenKind::Sharded,
+            epoch: epoch.clone(),
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let res = staking.send(
+        4,
+        StakingAction::AddEpoch {
+            pool: 0,
+            token: PROGRAMS[2].into(),
+            kind: TokenKind::Sharded,
+            epoch: Epoch { amount: 0, ..epoch },
+        },
+    );
+    assert!(res.contains(&(4, Err::<StakingEvent, Error>(Error::ZeroReward).encode())));
+
+    let res = staking.send(
+        4,
+        StakingAction::AddEpoch {
+            pool: 0,
+            token: PROGRAMS[2].into(),
+            kind: TokenKind::Sharded,
+            epoch: Epoch { end: 5000, ..epoch },
+        },
+    );
+    assert!(res.contains(&(4, Err::<StakingEvent, Error>(Error::InvalidEpoch).encode())));
+
+    let res = staking.send(
+        4,
+        StakingAction::AddEpoch {
+            pool: 0,
+            token: PROGRAMS[2].into(),
+            kind: TokenKind::Sharded,
+            epoch,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Err::<StakingEvent, Error>(Error::InsufficientFunds).encode()
+    )));
+
+    let res = staking.send(
+        4,
+        StakingAction::CancelEpoch {
+            pool: 0,
+            token: PROGRAMS[2].into(),
+            index: 0,
+        },
+    );
+    assert!(res.contains(&(4, Err::<StakingEvent, Error>(Error::EpochNotFound).encode())));
+}
+
+#[test]
+fn emission_curves() {
+    let sys = System::new();
+    init_staking(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    for curve in [
+        EmissionCurve::ExponentialDecay { half_life: 0 },
+        EmissionCurve::Halving { period: 0 },
+        EmissionCurve::Piecewise(vec![]),
+        EmissionCurve::Piecewise(vec![(5000, 1), (5000, 2)]),
+        EmissionCurve::Piecewise(vec![(0, 0), (5000, 0)]),
+        EmissionCurve::Piecewise(vec![(10000, 1)]),
+    ] {
+        let res = staking.send(
+            4,
+            StakingAction::CreatePool(InitStaking {
+                staking_token_address: PROGRAMS[1].into(),
+                reward_token_address: PROGRAMS[2].into(),
+                distribution_time: 10000,
+                reward_total: 1000,
+                curve,
+                staking_token_kind: TokenKind::Sharded,
+                reward_token_kind: TokenKind::Sharded,
+            }),
+        );
+        assert!(res.contains(&(4, Err::<StakingEvent, Error>(Error::InvalidCurve).encode())));
+    }
+}
+
+#[test]
+fn fixed_apr() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(
+        5,
+        StakingAction::SetFixedApr {
+            pool: 0,
+            token: PROGRAMS[1].into(),
+            kind: TokenKind::Sharded,
+            rate: 1000,
+            coverage: 10000,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let res = staking.send(
+        4,
+        StakingAction::SetFixedApr {
+            pool: 0,
+            token: PROGRAMS[1].into(),
+            kind: TokenKind::Sharded,
+            rate: 3_153_600_000,
+            coverage: 10000,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::FixedAprUpdated).encode()
+    )));
+
+    // The interest isn't funded
+    st_token.approve(5, staking.id().into_bytes(), 1000);
+    let res = staking.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::InsufficientFunds).encode()
+    )));
+}
+
+#[test]
+fn tiers() {
+    let sys = System::new();
+    init_staking(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(
+        5,
+        StakingAction::SetTiers {
+            pool: 0,
+            tiers: vec![(2000, 15000)],
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let res = staking.send(
+        4,
+        StakingAction::SetTiers {
+            pool: 0,
+            tiers: vec![(2000, 15000), (5000, 0)],
+        },
+    );
+    assert!(res.contains(&(4, Err::<StakingEvent, Error>(Error::InvalidTier).encode())));
+}
+
+#[test]
+fn loyalty() {
+    let sys = System::new();
+    init_staking(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let config = LoyaltyConfig {
+        step: 1000,
+        step_bonus: 1000,
+        max_bonus: 2000,
+    };
+
+    let res = staking.send(
+        5,
+        StakingAction::SetLoyalty {
+            pool: 0,
+            config: Some(config.clone()),
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let res = staking.send(
+        4,
+        StakingAction::SetLoyalty {
+            pool: 0,
+            config: Some(LoyaltyConfig { step: 0, ..config }),
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Err::<StakingEvent, Error>(Error::InvalidLoyalty).encode()
+    )));
+}
+
+#[test]
+fn stake_for() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(
+        7,
+        StakingAction::StakeFor {
+            pool: 0,
+            beneficiary: ActorId::zero(),
+            amount: 1000,
+            locked_until: None,
+        },
+    );
+    assert!(res.contains(&(7, Err::<StakingEvent, Error>(Error::ZeroAddress).encode())));
+
+    let time = sys.block_timestamp();
+
+    // The beneficiary can't be locked out for good
+    let res = staking.send(
+        7,
+        StakingAction::StakeFor {
+            pool: 0,
+            beneficiary: 5.into(),
+            amount: 1,
+            locked_until: Some(u64::MAX),
+        },
+    );
+    assert!(res.contains(&(
+        7,
+        Err::<StakingEvent, Error>(Error::InvalidLockTerm).encode()
+    )));
+
+    st_token.approve(7, staking.id().into_bytes(), 1000);
+    let res = staking.send(
+        7,
+        StakingAction::StakeFor {
+            pool: 0,
+            beneficiary: 5.into(),
+            amount: 1000,
+            locked_until: Some(time + 2000),
+        },
+    );
+    assert!(res.contains(&(
+        7,
+        Ok::<StakingEvent, Error>(StakingEvent::StakedFor {
+            beneficiary: 5.into(),
+            amount: 1000,
+            locked_until: Some(time + 2000),
+        })
+        .encode()
+    )));
+
+    let res = staking.send(
+        5,
+        StakingAction::Withdraw {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::LockedTokens).encode())));
+
+    let res = staking.send(5, StakingAction::EmergencyWithdraw { pool: 0 });
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::LockedTokens).encode())));
+}
+
+#[test]
+fn claimers() {
+    let sys = System::new();
+    init_staking(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(
+        7,
+        StakingAction::ClaimFor {
+            pool: 0,
+            staker: 5.into(),
+            token: None,
+        },
+    );
+    assert!(res.contains(&(7, Err::<StakingEvent, Error>(Error::NotClaimer).encode())));
+
+    let res = staking.send(5, StakingAction::RevokeClaimer(7.into()));
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotClaimer).encode())));
+
+    let res = staking.send(5, StakingAction::ApproveClaimer(ActorId::zero()));
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::ZeroAddress).encode())));
+
+    let res = staking.send(5, StakingAction::SetRewardRecipient(Some(ActorId::zero())));
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::ZeroAddress).encode())));
+
+    let res = staking.send(
+        5,
+        StakingAction::GetRewardTo {
+            pool: 0,
+            token: None,
+            recipient: ActorId::zero(),
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::ZeroAddress).encode())));
+}
+
+#[test]
+fn positions() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(
+        5,
+        StakingAction::SetPositionMode {
+            pool: 0,
+            enabled: true,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let res = staking.send(
+        4,
+        StakingAction::SetPositionMode {
+            pool: 0,
+            enabled: true,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PositionModeUpdated).encode()
+    )));
+
+    st_token.approve(5, staking.id().into_bytes(), 1000);
+    let res = staking.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::PositionMinted {
+            id: 0,
+            amount: 1000
+        })
+        .encode()
+    )));
+
+    let res = staking.send(
+        4,
+        StakingAction::SetPositionMode {
+            pool: 0,
+            enabled: false,
+        },
+    );
+    assert!(res.contains(&(4, Err::<StakingEvent, Error>(Error::PoolNotEmpty).encode())));
+
+    let res = staking.send(
+        6,
+        StakingAction::Position {
+            id: 0,
+            action: PositionAction::Withdraw { amount: 1000 },
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Err::<StakingEvent, Error>(Error::NotPositionOwner).encode()
+    )));
+
+    let res = staking.send(
+        5,
+        StakingAction::Position {
+            id: 1,
+            action: PositionAction::ClaimUnbonded,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::PositionNotFound).encode()
+    )));
+
+    let res = staking.send(
+        5,
+        StakingAction::TransferPosition {
+            id: 0,
+            to: ActorId::zero(),
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::ZeroAddress).encode())));
+
+    // The stake of a position only comes from its owner's deposits
+    st_token.approve(6, staking.id().into_bytes(), 1);
+    let res = staking.send(
+        6,
+        StakingAction::StakeFor {
+            pool: 0,
+            beneficiary: position_key(0),
+            amount: 1,
+            locked_until: None,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Err::<StakingEvent, Error>(Error::ReservedAddress).encode()
+    )));
+
+    let res = staking.send(
+        5,
+        StakingAction::TransferPosition {
+            id: 0,
+            to: position_key(0),
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::ReservedAddress).encode()
+    )));
+}
+
+#[test]
+fn transfer_stake() {
+    let sys = System::new();
+    init_staking(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(
+        5,
+        StakingAction::TransferStake {
+            pool: 0,
+            to: 7.into(),
+            amount: 500,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::StakerNotFound).encode()
+    )));
+
+    let res = staking.send(
+        5,
+        StakingAction::TransferStake {
+            pool: 0,
+            to: 5.into(),
+            amount: 500,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::SelfTransfer).encode())));
+
+    let res = staking.send(
+        5,
+        StakingAction::TransferStake {
+            pool: 0,
+            to: 7.into(),
+            amount: 0,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::ZeroAmount).encode())));
+
+    for to in [position_key(0), vault_key()] {
+        let res = staking.send(
+            5,
+            StakingAction::TransferStake {
+                pool: 0,
+                to,
+                amount: 500,
+            },
+        );
+        assert!(res.contains(&(
+            5,
+            Err::<StakingEvent, Error>(Error::ReservedAddress).encode()
+        )));
+    }
+}
+
+#[test]
+fn share_token() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    let mut share_token = FungibleToken::initialize_with_id(&sys, 10);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(
+        7,
+        StakingAction::SyncShares {
+            pool: 0,
+            account: 5.into(),
+        },
+    );
+    assert!(res.contains(&(7, Err::<StakingEvent, Error>(Error::NoShareToken).encode())));
+
+    let res = staking.send(
+        5,
+        StakingAction::SetShareToken {
+            pool: 0,
+            token: Some(10.into()),
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let res = staking.send(
+        4,
+        StakingAction::SetShareToken {
+            pool: 0,
+            token: Some(10.into()),
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::ShareTokenUpdated(Some(10.into()))).encode()
+    )));
+
+    st_token.approve(5, staking.id().into_bytes(), 1000);
+    let res = staking.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    // The stake can't leave without its shares
+    share_token.transfer(5, 6, 1000);
+    share_token.approve(5, staking.id().into_bytes(), 1000);
+    let res = staking.send(
+        5,
+        StakingAction::Withdraw {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::TransferTokens).encode()
+    )));
+
+    let res = staking.send(
+        5,
+        StakingAction::StakeLocked {
+            pool: 0,
+            amount: 1000,
+            term: 2000,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::ShareMode).encode())));
+
+    let res = staking.send(
+        5,
+        StakingAction::TransferStake {
+            pool: 0,
+            to: 6.into(),
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::ShareMode).encode())));
+
+    let res = staking.send(
+        4,
+        StakingAction::SetShareToken {
+            pool: 0,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(4, Err::<StakingEvent, Error>(Error::PoolNotEmpty).encode())));
+
+    // Nothing is owed while the rollbacks succeed
+    let res = staking.send(
+        7,
+        StakingAction::SettleOwed {
+            pool: 0,
+            account: 5.into(),
+        },
+    );
+    assert!(res.contains(&(7, Err::<StakingEvent, Error>(Error::ZeroAmount).encode())));
+}
+
+#[test]
+fn vault() {
+    let sys = System::new();
+    init_staking(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    let res = staking.send(
+        5,
+        StakingAction::VaultDeposit {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::RewardTokenNotFound).encode()
+    )));
+
+    let res = staking.send(5, StakingAction::VaultWithdraw { pool: 0, shares: 0 });
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::ZeroAmount).encode())));
+}
+
+#[test]
+fn native_staking() {
+    let sys = System::new();
+    init_staking(&sys);
+    init_staking_token(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    for (address, kind) in [
+        (ActorId::from(PROGRAMS[1]), TokenKind::Native),
+        (ActorId::zero(), TokenKind::Sharded),
+    ] {
+        let res = staking.send(
+            4,
+            StakingAction::CreatePool(InitStaking {
+                staking_token_address: address,
+                reward_token_address: PROGRAMS[2].into(),
+                distribution_time: 10000,
+                reward_total: 1000,
+                curve: EmissionCurve::Linear,
+                staking_token_kind: kind,
+                reward_token_kind: TokenKind::Sharded,
+            }),
+        );
+        assert!(res.contains(&(
+            4,
+            Err::<StakingEvent, Error>(Error::InvalidTokenKind).encode()
+        )));
+    }
+
+    // The sharded pool doesn't take value
+    sys.mint_to(5, 2000);
+    let res = staking.send_with_value(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+        1000,
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::InvalidValue).encode())));
+
+    let res = staking.send_with_value(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+        1000,
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::InvalidValue).encode())));
+}
+
+#[test]
+fn token_adapters() {
+    let sys = System::new();
+    init_staking(&sys);
+    sys.init_logger();
+    let staking = sys.get_program(1);
+
+    // The fungible token needs the address, and the known token keeps its kind
+    for (address, kind) in [
+        (ActorId::zero(), TokenKind::Fungible),
+        (ActorId::from(PROGRAMS[1]), TokenKind::Fungible),
+    ] {
+        let res = staking.send(
+            4,
+            StakingAction::CreatePool(InitStaking {
+                staking_token_address: address,
+                reward_token_address: PROGRAMS[2].into(),
+                distribution_time: 10000,
+                reward_total: 1000,
+                curve: EmissionCurve::Linear,
+                staking_token_kind: kind,
+                reward_token_kind: TokenKind::Sharded,
+            }),
+        );
+        assert!(res.contains(&(
+            4,
+            Err::<StakingEvent, Error>(Error::InvalidTokenKind).encode()
+        )));
+    }
+    // The reward tokens added outside the pool configuration keep their kinds too
+    let res = staking.send(
+        4,
+        StakingAction::SetFixedApr {
+            pool: 0,
+            token: PROGRAMS[2].into(),
+            kind: TokenKind::Fungible,
+            rate: 1000,
+            coverage: 0,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Err::<StakingEvent, Error>(Error::InvalidTokenKind).encode()
+    )));
+
+    let time = sys.block_timestamp();
+    let res = staking.send(
+        4,
+        StakingAction::AddEpoch {
+            pool: 0,
+            token: ActorId::zero(),
+            kind: TokenKind::Sharded,
+            epoch: Epoch {
+                start: time + 1000,
+                end: time + 2000,
+                amount: 1000,
+            },
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Err::<StakingEvent, Error>(Error::InvalidTokenKind).encode()
+    )));
+
+    // The gear fungible token only moves the approved funds
+    let mut token = LegacyFungibleToken::initialize_with_id(&sys, 11);
+    token.mint(4, 1000);
+
+    let res = staking.send(
+        4,
+        StakingAction::FundRewards {
+            token: 11.into(),
+            kind: TokenKind::Fungible,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Err::<StakingEvent, Error>(Error::TransferTokens).encode()
+    )));
+    token.balance(4).contains(1000);
+
+    token.approve(4, staking.id().into_bytes(), 1000);
+    let res = staking.send(
+        4,
+        StakingAction::FundRewards {
+            token: 11.into(),
+            kind: TokenKind::Fungible,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::RewardsFunded {
+            token: 11.into(),
+            amount: 1000,
+        })
+        .encode()
+    )));
 }
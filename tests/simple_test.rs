@@ -3,9 +3,13 @@ use gtest::{Program, System};
 use hashbrown::HashMap;
 use staking_io::*;
 mod utils;
-use utils::{FungibleToken, PROGRAMS};
+use utils::{FungibleToken, LegacyFungibleToken, PROGRAMS};
 
 const DECIMALS_FACTOR: u128 = 10_u128.pow(20);
+/// Beyond the user ids, so the share token doesn't take the owner's id
+const SHARE_TOKEN: u64 = 10;
+/// The gear fungible token, staked and paid through its adapter
+const LEGACY_TOKEN: u64 = 11;
 
 #[derive(Debug, Default)]
 struct Staking {
@@ -29,10 +33,16 @@ fn init_staking(sys: &System) {
             reward_token_address: PROGRAMS[2].into(),
             distribution_time: 10000,
             reward_total: 1000,
+            curve: EmissionCurve::Linear,
+            staking_token_kind: TokenKind::Sharded,
+            reward_token_kind: TokenKind::Sharded,
         },
     );
 
-    assert!(res.contains(&(4, Ok::<StakingEvent, Error>(StakingEvent::Updated).encode())));
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PoolCreated(0)).encode()
+    )));
 }
 
 fn init_staking_token(sys: &System) -> FungibleToken {
@@ -65,9 +75,38 @@ fn init_reward_token(sys: &System) -> FungibleToken {
     rw_token.mint(1, 100000);
     rw_token.balance(1).contains(100000);
 
+    rw_token.mint(4, 100000);
+    rw_token.balance(4).contains(100000);
+
     rw_token
 }
 
+fn reward_token() -> ActorId {
+    PROGRAMS[2].into()
+}
+
+/// Moves the owner's tokens to the program to fund the rewards
+fn fund_rewards(staking: &Program, token: &mut FungibleToken, address: ActorId, amount: u128) {
+    token.approve(4, staking.id().into_bytes(), amount);
+
+    let res = staking.send(
+        4,
+        StakingAction::FundRewards {
+            token: address,
+            kind: TokenKind::Sharded,
+            amount,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::RewardsFunded {
+            token: address,
+            amount,
+        })
+        .encode()
+    )));
+}
+
 /// Sets the reward to be distributed within distribution time
 /// param 'reward' The value of the distributed reward
 fn update_staking(staking: &mut Staking, reward: u128, time: u64) {
@@ -123,14 +162,26 @@ fn update_reward(staking: &mut Staking, time: u64) {
 /// Calculates the reward of the staker that is currently available
 fn calc_reward(staking: &mut Staking, source: &ActorId) -> u128 {
     if let Some(staker) = staking.stakers.get(source) {
-        return get_max_reward(staking, staker.balance) + staker.reward_allowed
-            - staker.reward_debt
-            - staker.distributed;
+        let reward = staker
+            .rewards
+            .get(&reward_token())
+            .cloned()
+            .unwrap_or_default();
+
+        return get_max_reward(staking, staker.effective_balance) + reward.reward_allowed
+            - reward.reward_debt
+            - reward.distributed;
     }
 
     panic!("calc_reward(): Staker {source:?} not found");
 }
 
+/// Records the reward sent to the staker
+fn distribute(staker: &mut Staker, reward: u128) {
+    let staker_reward = staker.rewards.entry(reward_token()).or_default();
+    staker_reward.distributed = staker_reward.distributed.saturating_add(reward);
+}
+
 #[test]
 fn stake() {
     let sys = System::new();
@@ -142,13 +193,25 @@ fn stake() {
 
     let id: ActorId = staking.id().into_bytes().into();
     st_token.approve(5, id, 1000);
-    let res = staking.send(5, StakingAction::Stake(1000));
+    let res = staking.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
     assert!(res.contains(&(
         5,
         Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
     )));
     st_token.approve(6, id, 3000);
-    let res = staking.send(6, StakingAction::Stake(3000));
+    let res = staking.send(
+        6,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 3000,
+        },
+    );
     assert!(res.contains(&(
         6,
         Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(3000)).encode()
@@ -160,18 +223,26 @@ fn update_staking_test() {
     let sys = System::new();
     init_staking(&sys);
     init_staking_token(&sys);
-    init_reward_token(&sys);
+    let mut rw_token = init_reward_token(&sys);
     sys.init_logger();
     let staking = sys.get_program(1);
 
+    fund_rewards(&staking, &mut rw_token, reward_token(), 1000);
+
     let res = staking.send(
         4,
-        StakingAction::UpdateStaking(InitStaking {
-            staking_token_address: PROGRAMS[1].into(),
-            reward_token_address: PROGRAMS[2].into(),
-            distribution_time: 10000,
-            reward_total: 1000,
-        }),
+        StakingAction::UpdateStaking {
+            pool: 0,
+            config: InitStaking {
+                staking_token_address: PROGRAMS[1].into(),
+                reward_token_address: PROGRAMS[2].into(),
+                distribution_time: 10000,
+                reward_total: 1000,
+                curve: EmissionCurve::Linear,
+                staking_token_kind: TokenKind::Sharded,
+                reward_token_kind: TokenKind::Sharded,
+            },
+        },
     );
     assert!(res.contains(&(4, Ok::<StakingEvent, Error>(StakingEvent::Updated).encode())));
 }
@@ -194,7 +265,13 @@ fn send_reward() {
     update_staking(&mut staking, 1000, time);
 
     st_token.approve(5, st.id().into_bytes(), 1500);
-    let res = st.send(5, StakingAction::Stake(1500));
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1500,
+        },
+    );
     assert!(res.contains(&(
         5,
         Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1500)).encode()
@@ -204,8 +281,16 @@ fn send_reward() {
     staking.stakers.insert(
         5.into(),
         Staker {
-            reward_debt: get_max_reward(&staking, 1500),
             balance: 1500,
+            effective_balance: 1500,
+            rewards: [(
+                reward_token(),
+                StakerReward {
+                    reward_debt: get_max_reward(&staking, 1500),
+                    ..Default::default()
+                },
+            )]
+            .into(),
             ..Default::default()
         },
     );
@@ -215,7 +300,13 @@ fn send_reward() {
     sys.spend_blocks(2);
 
     st_token.approve(6, st.id().into_bytes(), 2000);
-    let res = st.send(6, StakingAction::Stake(2000));
+    let res = st.send(
+        6,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 2000,
+        },
+    );
     assert!(res.contains(&(
         6,
         Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(2000)).encode()
@@ -225,8 +316,16 @@ fn send_reward() {
     staking.stakers.insert(
         6.into(),
         Staker {
-            reward_debt: get_max_reward(&staking, 2000),
             balance: 2000,
+            effective_balance: 2000,
+            rewards: [(
+                reward_token(),
+                StakerReward {
+                    reward_debt: get_max_reward(&staking, 2000),
+                    ..Default::default()
+                },
+            )]
+            .into(),
             ..Default::default()
         },
     );
@@ -241,9 +340,15 @@ fn send_reward() {
     staking
         .stakers
         .entry(5.into())
-        .and_modify(|stake| stake.distributed = stake.distributed.saturating_add(reward));
+        .and_modify(|stake| distribute(stake, reward));
 
-    let res = st.send(5, StakingAction::GetReward);
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
     println!(
         "Reward[4]: {:?} calc: {}, staking: {:?}",
         res.decoded_log::<StakingEvent>(),
@@ -252,7 +357,7 @@ fn send_reward() {
     );
     assert!(res.contains(&(
         5,
-        Ok::<StakingEvent, Error>(StakingEvent::Reward(reward)).encode()
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), reward)])).encode()
     )));
 
     sys.spend_blocks(1);
@@ -263,9 +368,15 @@ fn send_reward() {
     staking
         .stakers
         .entry(6.into())
-        .and_modify(|stake| stake.distributed = stake.distributed.saturating_add(reward));
+        .and_modify(|stake| distribute(stake, reward));
 
-    let res = st.send(6, StakingAction::GetReward);
+    let res = st.send(
+        6,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
     println!(
         "Reward[5]: {:?} calc: {}, staking: {:?}",
         res.decoded_log::<StakingEvent>(),
@@ -274,7 +385,7 @@ fn send_reward() {
     );
     assert!(res.contains(&(
         6,
-        Ok::<StakingEvent, Error>(StakingEvent::Reward(reward)).encode()
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), reward)])).encode()
     )));
 }
 
@@ -297,7 +408,13 @@ fn withdraw() {
     update_staking(&mut staking, 1000, time);
     let id: ActorId = st.id().into_bytes().into();
     st_token.approve(5, id, 1500);
-    let res = st.send(5, StakingAction::Stake(1500));
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1500,
+        },
+    );
     assert!(res.contains(&(
         5,
         Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1500)).encode()
@@ -307,8 +424,16 @@ fn withdraw() {
     staking.stakers.insert(
         5.into(),
         Staker {
-            reward_debt: get_max_reward(&staking, 1500),
             balance: 1500,
+            effective_balance: 1500,
+            rewards: [(
+                reward_token(),
+                StakerReward {
+                    reward_debt: get_max_reward(&staking, 1500),
+                    ..Default::default()
+                },
+            )]
+            .into(),
             ..Default::default()
         },
     );
@@ -318,7 +443,13 @@ fn withdraw() {
     sys.spend_blocks(2);
 
     st_token.approve(6, st.id().into_bytes(), 2000);
-    let res = st.send(6, StakingAction::Stake(2000));
+    let res = st.send(
+        6,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 2000,
+        },
+    );
     assert!(res.contains(&(
         6,
         Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(2000)).encode()
@@ -328,8 +459,16 @@ fn withdraw() {
     staking.stakers.insert(
         6.into(),
         Staker {
-            reward_debt: get_max_reward(&staking, 2000),
             balance: 2000,
+            effective_balance: 2000,
+            rewards: [(
+                reward_token(),
+                StakerReward {
+                    reward_debt: get_max_reward(&staking, 2000),
+                    ..Default::default()
+                },
+            )]
+            .into(),
             ..Default::default()
         },
     );
@@ -338,20 +477,32 @@ fn withdraw() {
 
     sys.spend_blocks(1);
 
-    let res = st.send(5, StakingAction::Withdraw(500));
+    let res = st.send(
+        5,
+        StakingAction::Withdraw {
+            pool: 0,
+            amount: 500,
+        },
+    );
     assert!(res.contains(&(
         5,
-        Ok::<StakingEvent, Error>(StakingEvent::Withdrawn(500)).encode()
+        Ok::<StakingEvent, Error>(StakingEvent::Withdrawn {
+            amount: 500,
+            penalty: 0,
+        })
+        .encode()
     )));
 
     update_reward(&mut staking, time + 3000);
-    let max_reward = get_max_reward(&staking, 500);
+    let max_reward = get_max_reward(&staking, 1500) - get_max_reward(&staking, 1000);
     let actor_id: &ActorId = &5.into();
     let opt = staking.stakers.get_mut(actor_id);
     if let Some(staker) = opt {
-        staker.reward_allowed = staker.reward_allowed.saturating_add(max_reward);
+        let reward = staker.rewards.entry(reward_token()).or_default();
+        reward.reward_allowed = reward.reward_allowed.saturating_add(max_reward);
 
         staker.balance = staker.balance.saturating_sub(500);
+        staker.effective_balance = staker.effective_balance.saturating_sub(500);
         staking.total_staked -= 500;
     }
 
@@ -363,12 +514,18 @@ fn withdraw() {
     staking
         .stakers
         .entry(5.into())
-        .and_modify(|stake| stake.distributed = stake.distributed.saturating_add(reward));
+        .and_modify(|stake| distribute(stake, reward));
 
-    let res = st.send(5, StakingAction::GetReward);
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
     assert!(res.contains(&(
         5,
-        Ok::<StakingEvent, Error>(StakingEvent::Reward(reward)).encode()
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), reward)])).encode()
     )));
     println!("Reward[4]: {:?}", res.decoded_log::<StakingEvent>());
 
@@ -380,12 +537,18 @@ fn withdraw() {
     staking
         .stakers
         .entry(6.into())
-        .and_modify(|stake| stake.distributed = stake.distributed.saturating_add(reward));
+        .and_modify(|stake| distribute(stake, reward));
 
-    let res = st.send(6, StakingAction::GetReward);
+    let res = st.send(
+        6,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
     assert!(res.contains(&(
         6,
-        Ok::<StakingEvent, Error>(StakingEvent::Reward(reward)).encode()
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), reward)])).encode()
     )));
     println!("Reward[5]: {:?}", res.decoded_log::<StakingEvent>());
 }
@@ -409,7 +572,13 @@ fn meta_tests() {
     update_staking(&mut staking, 1000, time);
 
     st_token.approve(5, st.id().into_bytes(), 1500);
-    let res = st.send(5, StakingAction::Stake(1500));
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1500,
+        },
+    );
     assert!(res.contains(&(
         5,
         Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1500)).encode()
@@ -419,8 +588,18 @@ fn meta_tests() {
     staking.stakers.insert(
         5.into(),
         Staker {
-            reward_debt: get_max_reward(&staking, 1500),
             balance: 1500,
+            staked_at: time,
+            since: time,
+            effective_balance: 1500,
+            rewards: [(
+                reward_token(),
+                StakerReward {
+                    reward_debt: get_max_reward(&staking, 1500),
+                    ..Default::default()
+                },
+            )]
+            .into(),
             ..Default::default()
         },
     );
@@ -430,7 +609,13 @@ fn meta_tests() {
     sys.spend_blocks(2);
 
     st_token.approve(6, st.id().into_bytes(), 2000);
-    let res = st.send(6, StakingAction::Stake(2000));
+    let res = st.send(
+        6,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 2000,
+        },
+    );
     assert!(res.contains(&(
         6,
         Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(2000)).encode()
@@ -440,8 +625,18 @@ fn meta_tests() {
     staking.stakers.insert(
         6.into(),
         Staker {
-            reward_debt: get_max_reward(&staking, 2000),
             balance: 2000,
+            staked_at: time + 2000,
+            since: time + 2000,
+            effective_balance: 2000,
+            rewards: [(
+                reward_token(),
+                StakerReward {
+                    reward_debt: get_max_reward(&staking, 2000),
+                    ..Default::default()
+                },
+            )]
+            .into(),
             ..Default::default()
         },
     );
@@ -449,16 +644,2912 @@ fn meta_tests() {
     staking.total_staked = 3500;
     let stakers: HashMap<ActorId, Staker> = staking.stakers.clone().into_iter().collect();
     let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[0];
 
-    assert_eq!(state.stakers.len(), stakers.len());
+    assert_eq!(pool.stakers.len(), stakers.len());
 
     let actor_id: &ActorId = &5.into();
     let staker = staking.stakers.get(actor_id).unwrap();
 
-    let (_id, state_staker) = state
+    let (_id, state_staker) = pool
         .stakers
         .iter()
         .find(|(id, _staker)| id.eq(actor_id))
         .expect("Can't find id");
     assert_eq!(state_staker, staker);
 }
+
+#[test]
+fn stake_locked() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    let res = st.send(
+        4,
+        StakingAction::SetLockTerms {
+            pool: 0,
+            terms: vec![(2000, 2 * BASIS_POINTS)],
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::LockTermsUpdated).encode()
+    )));
+
+    let time = sys.block_timestamp();
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::StakeLocked {
+            pool: 0,
+            amount: 1000,
+            term: 2000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeLocked {
+            amount: 1000,
+            unlock_time: time + 2000,
+        })
+        .encode()
+    )));
+
+    st_token.approve(6, st.id().into_bytes(), 1000);
+    let res = st.send(
+        6,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    sys.spend_blocks(1);
+
+    let res = st.send(
+        5,
+        StakingAction::Withdraw {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::LockedTokens).encode())));
+
+    // 100 tokens are produced, the locked stake weighs 2000 of 3000
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 66)])).encode()
+    )));
+
+    let res = st.send(
+        6,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 33)])).encode()
+    )));
+
+    sys.spend_blocks(1);
+
+    let res = st.send(
+        5,
+        StakingAction::Withdraw {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Withdrawn {
+            amount: 1000,
+            penalty: 0,
+        })
+        .encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[0];
+    assert_eq!(pool.total_staked, 1000);
+    assert!(pool.lock_expirations.is_empty());
+
+    // The emergency withdrawal leaves the locked tokens staked
+    let time = sys.block_timestamp();
+
+    st_token.approve(6, st.id().into_bytes(), 1000);
+    let res = st.send(
+        6,
+        StakingAction::StakeLocked {
+            pool: 0,
+            amount: 1000,
+            term: 2000,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeLocked {
+            amount: 1000,
+            unlock_time: time + 2000,
+        })
+        .encode()
+    )));
+
+    let res = st.send(6, StakingAction::EmergencyWithdraw { pool: 0 });
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::EmergencyWithdrawn(1000)).encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[0];
+    let stakers: HashMap<_, _> = pool.stakers.iter().cloned().collect();
+    assert_eq!(stakers[&6.into()].balance, 1000);
+    assert_eq!(stakers[&6.into()].locks.len(), 1);
+    assert_eq!(pool.total_staked, 2000);
+    assert_eq!(
+        pool.lock_expirations,
+        [(time + 2000, vec![(6.into(), 1000)])].into()
+    );
+
+    let res = st.send(6, StakingAction::EmergencyWithdraw { pool: 0 });
+    assert!(res.contains(&(6, Err::<StakingEvent, Error>(Error::LockedTokens).encode())));
+}
+
+#[test]
+fn pools() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    let mut rw_token = init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    // The funds cover the initial pool and the new one
+    fund_rewards(&st, &mut rw_token, reward_token(), 3000);
+
+    let res = st.send(
+        4,
+        StakingAction::CreatePool(InitStaking {
+            staking_token_address: PROGRAMS[1].into(),
+            reward_token_address: PROGRAMS[2].into(),
+            distribution_time: 10000,
+            reward_total: 2000,
+            curve: EmissionCurve::Linear,
+            staking_token_kind: TokenKind::Sharded,
+            reward_token_kind: TokenKind::Sharded,
+        }),
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PoolCreated(1)).encode()
+    )));
+
+    st_token.approve(5, st.id().into_bytes(), 2000);
+    for pool in [0, 1] {
+        let res = st.send(5, StakingAction::Stake { pool, amount: 1000 });
+        assert!(res.contains(&(
+            5,
+            Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+        )));
+    }
+
+    st_token.approve(6, st.id().into_bytes(), 1000);
+    let res = st.send(
+        6,
+        StakingAction::Stake {
+            pool: 1,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    sys.spend_blocks(1);
+
+    // Pool 0 has produced 100 tokens for 5, pool 1 - 200 tokens for 5 and 6
+    for (pool, staker) in [(0, 5_u64), (1, 5), (1, 6)] {
+        let res = st.send(staker, StakingAction::GetReward { pool, token: None });
+        assert!(res.contains(&(
+            staker,
+            Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 100)])).encode()
+        )));
+    }
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    assert_eq!(state.pools.len(), 2);
+
+    let (_id, pool) = &state.pools[1];
+    assert_eq!(pool.total_staked, 2000);
+    assert_eq!(pool.stakers.len(), 2);
+}
+
+#[test]
+fn multiple_rewards() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    fund_rewards(&st, &mut st_token, PROGRAMS[1].into(), 3000);
+
+    // The staking token is distributed as the second reward token
+    let res = st.send(
+        4,
+        StakingAction::UpdateStaking {
+            pool: 0,
+            config: InitStaking {
+                staking_token_address: PROGRAMS[1].into(),
+                reward_token_address: PROGRAMS[1].into(),
+                distribution_time: 10000,
+                reward_total: 3000,
+                curve: EmissionCurve::Linear,
+                staking_token_kind: TokenKind::Sharded,
+                reward_token_kind: TokenKind::Sharded,
+            },
+        },
+    );
+    assert!(res.contains(&(4, Ok::<StakingEvent, Error>(StakingEvent::Updated).encode())));
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    sys.spend_blocks(1);
+
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: Some(reward_token()),
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 100)])).encode()
+    )));
+
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(PROGRAMS[1].into(), 300)])).encode()
+    )));
+
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: Some(reward_token()),
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::ZeroReward).encode())));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[0];
+    assert_eq!(pool.rewards.len(), 2);
+}
+
+#[test]
+fn compound() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    fund_rewards(&st, &mut st_token, PROGRAMS[1].into(), 1000);
+
+    let res = st.send(
+        4,
+        StakingAction::CreatePool(InitStaking {
+            staking_token_address: PROGRAMS[1].into(),
+            reward_token_address: PROGRAMS[1].into(),
+            distribution_time: 10000,
+            reward_total: 1000,
+            curve: EmissionCurve::Linear,
+            staking_token_kind: TokenKind::Sharded,
+            reward_token_kind: TokenKind::Sharded,
+        }),
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PoolCreated(1)).encode()
+    )));
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 1,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, staker) = &state.pools[1].1.stakers[0];
+    let (staked_at, since) = (staker.staked_at, staker.since);
+
+    sys.spend_blocks(1);
+
+    let res = st.send(5, StakingAction::Compound { pool: 1 });
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Compounded { amount: 100 }).encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[1];
+    let (_id, staker) = &pool.stakers[0];
+    assert_eq!(staker.balance, 1100);
+    assert_eq!(staker.staked_at, staked_at);
+    assert_eq!(staker.since, since);
+    assert_eq!(pool.total_staked, 1100);
+
+    sys.spend_blocks(1);
+
+    let res = st.send(
+        5,
+        StakingAction::Withdraw {
+            pool: 1,
+            amount: 1100,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Withdrawn {
+            amount: 1100,
+            penalty: 0,
+        })
+        .encode()
+    )));
+
+    // The compounded tokens have earned their share of the next 100 tokens
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 1,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(PROGRAMS[1].into(), 99)])).encode()
+    )));
+}
+
+#[test]
+fn unbonding() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    let res = st.send(
+        4,
+        StakingAction::SetUnbondingPeriod {
+            pool: 0,
+            period: 2000,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::UnbondingPeriodUpdated).encode()
+    )));
+
+    let time = sys.block_timestamp();
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    for amount in [600, 400] {
+        let res = st.send(5, StakingAction::RequestUnstake { pool: 0, amount });
+        assert!(res.contains(&(
+            5,
+            Ok::<StakingEvent, Error>(StakingEvent::Unbonding {
+                amount,
+                penalty: 0,
+                maturity: time + 2000,
+            })
+            .encode()
+        )));
+    }
+
+    let res = st.send(5, StakingAction::Rebond { pool: 0, index: 1 });
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Rebonded(400)).encode()
+    )));
+
+    sys.spend_blocks(1);
+
+    let res = st.send(5, StakingAction::ClaimUnbonded { pool: 0 });
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::UnbondingNotMatured).encode()
+    )));
+
+    sys.spend_blocks(1);
+
+    let res = st.send(5, StakingAction::ClaimUnbonded { pool: 0 });
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Withdrawn {
+            amount: 600,
+            penalty: 0,
+        })
+        .encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[0];
+    let (_id, staker) = &pool.stakers[0];
+    assert_eq!(staker.balance, 400);
+    assert!(staker.unbonding.is_empty());
+    assert_eq!(pool.total_staked, 400);
+}
+
+#[test]
+fn penalty() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    let res = st.send(
+        4,
+        StakingAction::SetPenalty {
+            pool: 0,
+            config: Some(PenaltyConfig {
+                rate: 1000,
+                holding_period: 10000,
+                destination: PenaltyDestination::Treasury(7.into()),
+            }),
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PenaltyUpdated).encode()
+    )));
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    sys.spend_blocks(5);
+
+    // The penalty rate has decayed to 5% by the middle of the holding period
+    let res = st.send(
+        5,
+        StakingAction::Withdraw {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Withdrawn {
+            amount: 1000,
+            penalty: 50,
+        })
+        .encode()
+    )));
+
+    st_token.balance(5).contains(9950);
+    st_token.balance(7).contains(20050);
+}
+
+#[test]
+fn penalty_to_stakers() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    let res = st.send(
+        4,
+        StakingAction::SetPenalty {
+            pool: 0,
+            config: Some(PenaltyConfig {
+                rate: 1000,
+                holding_period: 10000,
+                destination: PenaltyDestination::Stakers,
+            }),
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PenaltyUpdated).encode()
+    )));
+
+    for staker in [5, 6] {
+        st_token.approve(staker, st.id().into_bytes(), 1000);
+        let res = st.send(
+            staker,
+            StakingAction::Stake {
+                pool: 0,
+                amount: 1000,
+            },
+        );
+        assert!(res.contains(&(
+            staker,
+            Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+        )));
+    }
+
+    sys.spend_blocks(5);
+
+    let res = st.send(
+        5,
+        StakingAction::Withdraw {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Withdrawn {
+            amount: 1000,
+            penalty: 50,
+        })
+        .encode()
+    )));
+
+    // The whole penalty goes to the only remaining staker
+    let res = st.send(
+        6,
+        StakingAction::GetReward {
+            pool: 0,
+            token: Some(PROGRAMS[1].into()),
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(PROGRAMS[1].into(), 50)])).encode()
+    )));
+
+    // The penalties don't make the staking token a reward token
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[0];
+    assert_eq!(pool.rewards.len(), 1);
+    assert_eq!(pool.penalty_per_stake, 50 * DECIMALS_FACTOR / 1000);
+
+    let res = st.send(
+        4,
+        StakingAction::SetTiers {
+            pool: 0,
+            tiers: vec![(1000, 20000)],
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::TiersUpdated).encode()
+    )));
+
+    st_token.approve(6, st.id().into_bytes(), 1000);
+    let res = st.send(
+        6,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    // The weighted sole staker has nobody to pay the penalty to
+
+    let res = st.send(
+        6,
+        StakingAction::Withdraw {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::Withdrawn {
+            amount: 1000,
+            penalty: 0,
+        })
+        .encode()
+    )));
+}
+
+#[test]
+fn emergency_withdraw() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    for staker in [5, 6] {
+        st_token.approve(staker, st.id().into_bytes(), 1000);
+        let res = st.send(
+            staker,
+            StakingAction::Stake {
+                pool: 0,
+                amount: 1000,
+            },
+        );
+        assert!(res.contains(&(
+            staker,
+            Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+        )));
+    }
+
+    sys.spend_blocks(1);
+
+    let res = st.send(5, StakingAction::EmergencyWithdraw { pool: 0 });
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::EmergencyWithdrawn(1000)).encode()
+    )));
+    st_token.balance(5).contains(10000);
+
+    // The forfeited reward is redistributed to the remaining staker
+    let res = st.send(
+        6,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 100)])).encode()
+    )));
+
+    let res = st.send(
+        4,
+        StakingAction::SetForfeitDestination {
+            pool: 0,
+            destination: ForfeitDestination::Owner,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::ForfeitDestinationUpdated).encode()
+    )));
+
+    st_token.approve(7, st.id().into_bytes(), 1000);
+    let res = st.send(
+        7,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        7,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    sys.spend_blocks(1);
+
+    let res = st.send(7, StakingAction::EmergencyWithdraw { pool: 0 });
+    assert!(res.contains(&(
+        7,
+        Ok::<StakingEvent, Error>(StakingEvent::EmergencyWithdrawn(1000)).encode()
+    )));
+
+    // The forfeited reward is kept for the owner apart from the stakers
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[0];
+    assert_eq!(pool.forfeited, [(reward_token(), 50)].into());
+    assert!(!pool.stakers.iter().any(|(id, _)| *id == 4.into()));
+
+    let res = st.send(
+        4,
+        StakingAction::ClaimForfeited {
+            pool: 0,
+            recipient: 8.into(),
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::ForfeitedClaimed(vec![(reward_token(), 50)]))
+            .encode()
+    )));
+
+    // The emergency withdrawal keeps the unbonding delay and pays the early withdrawal penalty
+    let res = st.send(
+        4,
+        StakingAction::SetPenalty {
+            pool: 0,
+            config: Some(PenaltyConfig {
+                rate: 1000,
+                holding_period: 10000,
+                destination: PenaltyDestination::Treasury(7.into()),
+            }),
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PenaltyUpdated).encode()
+    )));
+
+    let res = st.send(
+        4,
+        StakingAction::SetUnbondingPeriod {
+            pool: 0,
+            period: 3000,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::UnbondingPeriodUpdated).encode()
+    )));
+
+    st_token.approve(8, st.id().into_bytes(), 1000);
+    let res = st.send(
+        8,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        8,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    sys.spend_blocks(5);
+
+    let time = sys.block_timestamp();
+
+    let res = st.send(
+        8,
+        StakingAction::RequestUnstake {
+            pool: 0,
+            amount: 500,
+        },
+    );
+    assert!(res.contains(&(
+        8,
+        Ok::<StakingEvent, Error>(StakingEvent::Unbonding {
+            amount: 500,
+            penalty: 25,
+            maturity: time + 3000,
+        })
+        .encode()
+    )));
+
+    // The rest of the stake starts unbonding, nothing is paid before the maturity
+    let res = st.send(8, StakingAction::EmergencyWithdraw { pool: 0 });
+    assert!(res.contains(&(
+        8,
+        Ok::<StakingEvent, Error>(StakingEvent::EmergencyWithdrawn(0)).encode()
+    )));
+
+    let res = st.send(8, StakingAction::EmergencyWithdraw { pool: 0 });
+    assert!(res.contains(&(
+        8,
+        Err::<StakingEvent, Error>(Error::UnbondingNotMatured).encode()
+    )));
+
+    sys.spend_blocks(3);
+
+    let res = st.send(8, StakingAction::EmergencyWithdraw { pool: 0 });
+    assert!(res.contains(&(
+        8,
+        Ok::<StakingEvent, Error>(StakingEvent::EmergencyWithdrawn(950)).encode()
+    )));
+    st_token.balance(8).contains(19950);
+    st_token.balance(7).contains(20050);
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[0];
+    assert!(!pool.stakers.iter().any(|(id, _)| *id == 8.into()));
+}
+
+#[test]
+fn pause() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    let mask = ActionMask {
+        withdraw: true,
+        claim: true,
+        ..Default::default()
+    };
+    let res = st.send(4, StakingAction::Pause(mask));
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::Paused(mask)).encode()
+    )));
+
+    // Pausing another group keeps the paused ones
+    let res = st.send(
+        4,
+        StakingAction::Pause(ActionMask {
+            stake: true,
+            ..Default::default()
+        }),
+    );
+    let paused = ActionMask {
+        stake: true,
+        ..mask
+    };
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::Paused(paused)).encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    assert_eq!(state.paused, paused);
+
+    sys.spend_blocks(1);
+
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::Paused).encode())));
+
+    sys.spend_blocks(1);
+
+    let res = st.send(
+        4,
+        StakingAction::Unpause(ActionMask {
+            claim: true,
+            ..Default::default()
+        }),
+    );
+    let paused = ActionMask {
+        claim: false,
+        ..paused
+    };
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::Unpaused(paused)).encode()
+    )));
+
+    // The reward kept accruing during the pause
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 200)])).encode()
+    )));
+
+    let res = st.send(
+        5,
+        StakingAction::Withdraw {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::Paused).encode())));
+}
+
+#[test]
+fn ownership() {
+    let sys = System::new();
+    init_staking(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    let res = st.send(4, StakingAction::TransferOwnership(5.into()));
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::OwnershipTransferStarted(5.into())).encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    assert_eq!(state.owner, 4.into());
+    assert_eq!(state.pending_owner, Some(5.into()));
+
+    let res = st.send(5, StakingAction::AcceptOwnership);
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::OwnershipTransferred(5.into())).encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    assert_eq!(state.owner, 5.into());
+    assert_eq!(state.pending_owner, None);
+
+    let res = st.send(5, StakingAction::RenounceOwnership);
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::OwnershipRenounced).encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    assert_eq!(state.owner, ActorId::zero());
+}
+
+#[test]
+fn roles() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut rw_token = init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    fund_rewards(&st, &mut rw_token, reward_token(), 2000);
+
+    let res = st.send(
+        4,
+        StakingAction::GrantRole {
+            actor: 5.into(),
+            role: Role::RewardManager,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::RoleGranted {
+            actor: 5.into(),
+            role: Role::RewardManager,
+        })
+        .encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    assert!(state.roles[&5.into()].contains(&Role::RewardManager));
+
+    let config = InitStaking {
+        staking_token_address: PROGRAMS[1].into(),
+        reward_token_address: PROGRAMS[2].into(),
+        distribution_time: 10000,
+        reward_total: 2000,
+        curve: EmissionCurve::Linear,
+        staking_token_kind: TokenKind::Sharded,
+        reward_token_kind: TokenKind::Sharded,
+    };
+    let res = st.send(
+        5,
+        StakingAction::UpdateStaking {
+            pool: 0,
+            config: config.clone(),
+        },
+    );
+    assert!(res.contains(&(5, Ok::<StakingEvent, Error>(StakingEvent::Updated).encode())));
+
+    // An admin manages the other roles
+    let res = st.send(
+        4,
+        StakingAction::GrantRole {
+            actor: 6.into(),
+            role: Role::Admin,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::RoleGranted {
+            actor: 6.into(),
+            role: Role::Admin,
+        })
+        .encode()
+    )));
+
+    let res = st.send(
+        6,
+        StakingAction::RevokeRole {
+            actor: 5.into(),
+            role: Role::RewardManager,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::RoleRevoked {
+            actor: 5.into(),
+            role: Role::RewardManager,
+        })
+        .encode()
+    )));
+
+    let res = st.send(5, StakingAction::UpdateStaking { pool: 0, config });
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::NotOwner).encode())));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    assert!(!state.roles.contains_key(&5.into()));
+}
+
+#[test]
+fn fund_rewards_test() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    let mut rw_token = init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    fund_rewards(&st, &mut rw_token, reward_token(), 2000);
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    sys.spend_blocks(1);
+
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 100)])).encode()
+    )));
+
+    // The unproduced 900 tokens of the initial schedule are released
+    let res = st.send(
+        4,
+        StakingAction::UpdateStaking {
+            pool: 0,
+            config: InitStaking {
+                staking_token_address: PROGRAMS[1].into(),
+                reward_token_address: PROGRAMS[2].into(),
+                distribution_time: 10000,
+                reward_total: 1900,
+                curve: EmissionCurve::Linear,
+                staking_token_kind: TokenKind::Sharded,
+                reward_token_kind: TokenKind::Sharded,
+            },
+        },
+    );
+    assert!(res.contains(&(4, Ok::<StakingEvent, Error>(StakingEvent::Updated).encode())));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    assert_eq!(
+        state.funds[&reward_token()],
+        RewardFunds {
+            funded: 2000,
+            allocated: 2000,
+            claimed: 100,
+        }
+    );
+}
+
+#[test]
+fn notify_reward_amount() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    let mut rw_token = init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    fund_rewards(&st, &mut rw_token, reward_token(), 2000);
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    sys.spend_blocks(5);
+
+    let res = st.send(
+        4,
+        StakingAction::NotifyRewardAmount {
+            pool: 0,
+            token: reward_token(),
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::RewardNotified {
+            token: reward_token(),
+            amount: 1000,
+        })
+        .encode()
+    )));
+
+    sys.spend_blocks(1);
+
+    // The 500 unproduced tokens and the 1000 added ones are distributed within 10 blocks
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 650)])).encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    assert_eq!(state.funds[&reward_token()].allocated, 2000);
+}
+
+#[test]
+fn epochs() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    let mut rw_token = init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    fund_rewards(&st, &mut rw_token, reward_token(), 2500);
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    for epoch in [
+        Epoch {
+            start: 5000,
+            end: 10000,
+            amount: 1000,
+        },
+        Epoch {
+            start: 20000,
+            end: 30000,
+            amount: 500,
+        },
+    ] {
+        let res = st.send(
+            4,
+            StakingAction::AddEpoch {
+                pool: 0,
+                token: reward_token(),
+                kind: TokenKind::Sharded,
+                epoch: epoch.clone(),
+            },
+        );
+        assert!(res.contains(&(
+            4,
+            Ok::<StakingEvent, Error>(StakingEvent::EpochAdded {
+                token: reward_token(),
+                epoch,
+            })
+            .encode()
+        )));
+    }
+
+    let res = st.send(
+        4,
+        StakingAction::CancelEpoch {
+            pool: 0,
+            token: reward_token(),
+            index: 1,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::EpochCancelled {
+            token: reward_token(),
+            epoch: Epoch {
+                start: 20000,
+                end: 30000,
+                amount: 500,
+            },
+        })
+        .encode()
+    )));
+
+    sys.spend_blocks(6);
+
+    let res = st.send(
+        4,
+        StakingAction::CancelEpoch {
+            pool: 0,
+            token: reward_token(),
+            index: 0,
+        },
+    );
+    assert!(res.contains(&(4, Err::<StakingEvent, Error>(Error::EpochStarted).encode())));
+
+    // 600 tokens of the initial schedule and 200 tokens of the epoch
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 800)])).encode()
+    )));
+
+    sys.spend_blocks(5);
+
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 1200)])).encode()
+    )));
+
+    // The finished epoch is removed from the schedule
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[0];
+    let (_id, reward) = &pool.rewards[0];
+    assert!(reward.epochs.is_empty());
+    assert_eq!(state.funds[&reward_token()].allocated, 2000);
+}
+
+#[test]
+fn emission_curves() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    let mut rw_token = init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    fund_rewards(&st, &mut rw_token, reward_token(), 4000);
+
+    let curves = [
+        EmissionCurve::Halving { period: 5000 },
+        EmissionCurve::ExponentialDecay { half_life: 5000 },
+        EmissionCurve::Piecewise(vec![(0, 3), (5000, 1)]),
+    ];
+
+    st_token.approve(5, st.id().into_bytes(), 3000);
+    for (pool, curve) in (1..).zip(curves) {
+        let res = st.send(
+            4,
+            StakingAction::CreatePool(InitStaking {
+                staking_token_address: PROGRAMS[1].into(),
+                reward_token_address: PROGRAMS[2].into(),
+                distribution_time: 10000,
+                reward_total: 1000,
+                curve,
+                staking_token_kind: TokenKind::Sharded,
+                reward_token_kind: TokenKind::Sharded,
+            }),
+        );
+        assert!(res.contains(&(
+            4,
+            Ok::<StakingEvent, Error>(StakingEvent::PoolCreated(pool)).encode()
+        )));
+
+        let res = st.send(5, StakingAction::Stake { pool, amount: 1000 });
+        assert!(res.contains(&(
+            5,
+            Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+        )));
+    }
+
+    let distributed = |pool: usize| {
+        let state: IoStaking = st.read_state().expect("Can't read state");
+        let (_id, pool) = &state.pools[pool];
+        let (_id, staker) = &pool.stakers[0];
+        staker.rewards[&reward_token()].distributed
+    };
+
+    sys.spend_blocks(5);
+
+    for pool in 1..=3 {
+        st.send(5, StakingAction::GetReward { pool, token: None });
+    }
+
+    // 1000 * 1 / 1.5 for the halving, about 1000 * 2 / 3 for the decay
+    // and 1000 * 15000 / 20000 for the piecewise curve
+    assert_eq!(distributed(1), 666);
+    assert!((660..=670).contains(&distributed(2)));
+    assert_eq!(distributed(3), 750);
+
+    sys.spend_blocks(6);
+
+    // The whole reward is produced by the end of the distribution, but no more
+    for pool in 1..=3 {
+        st.send(5, StakingAction::GetReward { pool, token: None });
+        assert_eq!(distributed(pool as usize), 1000);
+    }
+}
+
+#[test]
+fn fixed_apr() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    fund_rewards(&st, &mut st_token, PROGRAMS[1].into(), 150);
+
+    // 1% of the balance per block
+    let res = st.send(
+        4,
+        StakingAction::SetFixedApr {
+            pool: 0,
+            token: PROGRAMS[1].into(),
+            kind: TokenKind::Sharded,
+            rate: 3_153_600_000,
+            coverage: 10000,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::FixedAprUpdated).encode()
+    )));
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    // The funds can't cover the interest of one more stake for the next 10 blocks
+    st_token.approve(6, st.id().into_bytes(), 1000);
+    let res = st.send(
+        6,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Err::<StakingEvent, Error>(Error::InsufficientFunds).encode()
+    )));
+
+    sys.spend_blocks(2);
+
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: Some(PROGRAMS[1].into()),
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(PROGRAMS[1].into(), 20)])).encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    assert_eq!(state.liabilities[&PROGRAMS[1].into()], 120);
+
+    // The reserve for the interest can't be allocated to a reward schedule
+    let res = st.send(
+        4,
+        StakingAction::UpdateStaking {
+            pool: 0,
+            config: InitStaking {
+                staking_token_address: PROGRAMS[1].into(),
+                reward_token_address: PROGRAMS[1].into(),
+                distribution_time: 10000,
+                reward_total: 31,
+                curve: EmissionCurve::Linear,
+                staking_token_kind: TokenKind::Sharded,
+                reward_token_kind: TokenKind::Sharded,
+            },
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Err::<StakingEvent, Error>(Error::InsufficientFunds).encode()
+    )));
+
+    // The restaked interest is a new stake, so the reserve must cover the interest on it
+    sys.spend_blocks(3);
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    assert_eq!(state.liabilities[&PROGRAMS[1].into()], 150);
+
+    let res = st.send(5, StakingAction::Compound { pool: 0 });
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::InsufficientFunds).encode()
+    )));
+}
+
+#[test]
+fn tiers() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    // The stakes from 2000 tokens earn twice as much
+    let res = st.send(
+        4,
+        StakingAction::SetTiers {
+            pool: 0,
+            tiers: vec![(2000, 20000)],
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::TiersUpdated).encode()
+    )));
+
+    for (staker, amount) in [(5, 1000), (6, 2000)] {
+        st_token.approve(staker, st.id().into_bytes(), amount);
+        let res = st.send(staker, StakingAction::Stake { pool: 0, amount });
+        assert!(res.contains(&(
+            staker,
+            Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(amount)).encode()
+        )));
+    }
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[0];
+    assert_eq!(pool.total_staked, 5000);
+
+    sys.spend_blocks(1);
+
+    // The reward is settled at the old weight before the tier changes
+    let res = st.send(
+        6,
+        StakingAction::Withdraw {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::Withdrawn {
+            amount: 1000,
+            penalty: 0,
+        })
+        .encode()
+    )));
+
+    sys.spend_blocks(1);
+
+    for (staker, reward) in [(5, 70), (6, 130)] {
+        let res = st.send(
+            staker,
+            StakingAction::GetReward {
+                pool: 0,
+                token: None,
+            },
+        );
+        assert!(res.contains(&(
+            staker,
+            Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), reward)]))
+                .encode()
+        )));
+    }
+
+    // The new tiers are applied when the stakers interact with the pool
+    let res = st.send(
+        4,
+        StakingAction::SetTiers {
+            pool: 0,
+            tiers: vec![(2000, 30000)],
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::TiersUpdated).encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[0];
+    assert_eq!(pool.total_staked, 2000);
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[0];
+    assert_eq!(pool.total_staked, 7000);
+}
+
+#[test]
+fn weight_rounding() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    for (staker, amount) in [(5, 2), (6, 1)] {
+        st_token.approve(staker, st.id().into_bytes(), amount);
+        let res = st.send(staker, StakingAction::Stake { pool: 0, amount });
+        assert!(res.contains(&(
+            staker,
+            Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(amount)).encode()
+        )));
+    }
+
+    // 66.67 tokens per stake are rounded down for every weight
+    sys.spend_blocks(2);
+
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 133)])).encode()
+    )));
+
+    let res = st.send(5, StakingAction::Withdraw { pool: 0, amount: 1 });
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Withdrawn {
+            amount: 1,
+            penalty: 0,
+        })
+        .encode()
+    )));
+
+    // The weight left after the withdrawal has nothing more to claim
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(5, Err::<StakingEvent, Error>(Error::ZeroReward).encode())));
+}
+
+#[test]
+fn loyalty() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    // +10% for every block of staking, up to +20%
+    let res = st.send(
+        4,
+        StakingAction::SetLoyalty {
+            pool: 0,
+            config: Some(LoyaltyConfig {
+                step: 1000,
+                step_bonus: 1000,
+                max_bonus: 2000,
+            }),
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::LoyaltyUpdated).encode()
+    )));
+
+    for staker in [5, 6] {
+        st_token.approve(staker, st.id().into_bytes(), 1000);
+        let res = st.send(
+            staker,
+            StakingAction::Stake {
+                pool: 0,
+                amount: 1000,
+            },
+        );
+        assert!(res.contains(&(
+            staker,
+            Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+        )));
+    }
+
+    sys.spend_blocks(3);
+
+    // The reward is settled at the old weight before the multiplier is applied
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 150)])).encode()
+    )));
+
+    // Withdrawing a half of the stake halves the staking time
+    let res = st.send(
+        6,
+        StakingAction::Withdraw {
+            pool: 0,
+            amount: 500,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::Withdrawn {
+            amount: 500,
+            penalty: 0,
+        })
+        .encode()
+    )));
+
+    let res = st.send(
+        6,
+        StakingAction::GetReward {
+            pool: 0,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 150)])).encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[0];
+    let stakers: HashMap<_, _> = pool.stakers.iter().cloned().collect();
+    assert_eq!(stakers[&5.into()].loyalty_weight, 12000);
+    assert_eq!(stakers[&6.into()].loyalty_weight, 11000);
+    assert_eq!(stakers[&6.into()].since, 1500);
+    assert_eq!(pool.total_staked, 1200 + 550);
+}
+
+#[test]
+fn stake_for() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    let time = sys.block_timestamp();
+
+    st_token.approve(7, st.id().into_bytes(), 1000);
+    let res = st.send(
+        7,
+        StakingAction::StakeFor {
+            pool: 0,
+            beneficiary: 5.into(),
+            amount: 1000,
+            locked_until: Some(time + 2000),
+        },
+    );
+    assert!(res.contains(&(
+        7,
+        Ok::<StakingEvent, Error>(StakingEvent::StakedFor {
+            beneficiary: 5.into(),
+            amount: 1000,
+            locked_until: Some(time + 2000),
+        })
+        .encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[0];
+    let stakers: HashMap<_, _> = pool.stakers.iter().cloned().collect();
+    assert_eq!(stakers[&5.into()].balance, 1000);
+    assert!(!stakers.contains_key(&7.into()));
+
+    sys.spend_blocks(2);
+
+    let res = st.send(
+        5,
+        StakingAction::Withdraw {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Withdrawn {
+            amount: 1000,
+            penalty: 0,
+        })
+        .encode()
+    )));
+
+    // The dust locked by a third party doesn't hold the rest of the stake
+    st_token.approve(6, st.id().into_bytes(), 1000);
+    let res = st.send(
+        6,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    let time = sys.block_timestamp();
+
+    st_token.approve(7, st.id().into_bytes(), 1);
+    let res = st.send(
+        7,
+        StakingAction::StakeFor {
+            pool: 0,
+            beneficiary: 6.into(),
+            amount: 1,
+            locked_until: Some(time + 2000),
+        },
+    );
+    assert!(res.contains(&(
+        7,
+        Ok::<StakingEvent, Error>(StakingEvent::StakedFor {
+            beneficiary: 6.into(),
+            amount: 1,
+            locked_until: Some(time + 2000),
+        })
+        .encode()
+    )));
+
+    let res = st.send(6, StakingAction::EmergencyWithdraw { pool: 0 });
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::EmergencyWithdrawn(1000)).encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[0];
+    let stakers: HashMap<_, _> = pool.stakers.iter().cloned().collect();
+    assert_eq!(stakers[&6.into()].balance, 1);
+    assert_eq!(pool.total_staked, 1);
+}
+
+#[test]
+fn claimers() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    let rw_token = init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    let res = st.send(5, StakingAction::ApproveClaimer(7.into()));
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::ClaimerApproved(7.into())).encode()
+    )));
+
+    let res = st.send(5, StakingAction::SetRewardRecipient(Some(9.into())));
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::RewardRecipientUpdated(Some(9.into()))).encode()
+    )));
+
+    sys.spend_blocks(2);
+
+    // The claimer triggers the claim, but the reward goes to the staker's recipient
+    let res = st.send(
+        7,
+        StakingAction::ClaimFor {
+            pool: 0,
+            staker: 5.into(),
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        7,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 200)])).encode()
+    )));
+    rw_token.balance(9).contains(200);
+    rw_token.balance(7).contains(0);
+
+    sys.spend_blocks(1);
+
+    let res = st.send(
+        5,
+        StakingAction::GetRewardTo {
+            pool: 0,
+            token: None,
+            recipient: 8.into(),
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 100)])).encode()
+    )));
+    rw_token.balance(8).contains(100);
+
+    let res = st.send(5, StakingAction::RevokeClaimer(7.into()));
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::ClaimerRevoked(7.into())).encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    assert!(state.claimers.is_empty());
+    assert_eq!(state.reward_recipients, [(5.into(), 9.into())].into());
+}
+
+#[test]
+fn positions() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    let rw_token = init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    let res = st.send(
+        4,
+        StakingAction::SetPositionMode {
+            pool: 0,
+            enabled: true,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PositionModeUpdated).encode()
+    )));
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::PositionMinted {
+            id: 0,
+            amount: 1000
+        })
+        .encode()
+    )));
+
+    sys.spend_blocks(2);
+
+    // The reward accrued so far goes to the previous owner
+    let res = st.send(
+        5,
+        StakingAction::TransferPosition {
+            id: 0,
+            to: 6.into(),
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::PositionTransferred {
+            id: 0,
+            from: 5.into(),
+            to: 6.into(),
+        })
+        .encode()
+    )));
+    rw_token.balance(5).contains(200);
+
+    sys.spend_blocks(1);
+
+    let res = st.send(
+        6,
+        StakingAction::Position {
+            id: 0,
+            action: PositionAction::GetReward { token: None },
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 100)])).encode()
+    )));
+    rw_token.balance(6).contains(100);
+
+    let res = st.send(
+        6,
+        StakingAction::Position {
+            id: 0,
+            action: PositionAction::Withdraw { amount: 1000 },
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::Withdrawn {
+            amount: 1000,
+            penalty: 0,
+        })
+        .encode()
+    )));
+    st_token.balance(6).contains(21000);
+
+    // The position is burnt once it has nothing left
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    assert!(state.positions.is_empty());
+    let (_id, pool) = &state.pools[0];
+    assert!(pool.stakers.is_empty());
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 0,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::PositionMinted {
+            id: 1,
+            amount: 1000
+        })
+        .encode()
+    )));
+
+    let res = st.send(
+        5,
+        StakingAction::Position {
+            id: 1,
+            action: PositionAction::EmergencyWithdraw,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::EmergencyWithdrawn(1000)).encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    assert!(state.positions.is_empty());
+}
+
+#[test]
+fn transfer_stake() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    let time = sys.block_timestamp();
+
+    for staker in [5, 6] {
+        st_token.approve(staker, st.id().into_bytes(), 1000);
+        let res = st.send(
+            staker,
+            StakingAction::Stake {
+                pool: 0,
+                amount: 1000,
+            },
+        );
+        assert!(res.contains(&(
+            staker,
+            Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+        )));
+    }
+
+    sys.spend_blocks(2);
+
+    let res = st.send(
+        5,
+        StakingAction::TransferStake {
+            pool: 0,
+            to: 7.into(),
+            amount: 500,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeTransferred {
+            to: 7.into(),
+            amount: 500,
+        })
+        .encode()
+    )));
+
+    sys.spend_blocks(1);
+
+    // The reward accrued before the transfer stays with the sender
+    for (staker, reward) in [(5, 125), (6, 150), (7, 25)] {
+        let res = st.send(
+            staker,
+            StakingAction::GetReward {
+                pool: 0,
+                token: None,
+            },
+        );
+        assert!(res.contains(&(
+            staker,
+            Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), reward)]))
+                .encode()
+        )));
+    }
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[0];
+    let stakers: HashMap<_, _> = pool.stakers.iter().cloned().collect();
+    assert_eq!(stakers[&5.into()].balance, 500);
+    assert_eq!(stakers[&7.into()].balance, 500);
+    assert_eq!(stakers[&7.into()].staked_at, time);
+    assert_eq!(pool.total_staked, 2000);
+}
+
+#[test]
+fn share_token() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    let mut share_token = FungibleToken::initialize_with_id(&sys, SHARE_TOKEN);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    let res = st.send(
+        4,
+        StakingAction::SetShareToken {
+            pool: 0,
+            token: Some(SHARE_TOKEN.into()),
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::ShareTokenUpdated(Some(SHARE_TOKEN.into())))
+            .encode()
+    )));
+
+    for staker in [5, 6] {
+        st_token.approve(staker, st.id().into_bytes(), 1000);
+        let res = st.send(
+            staker,
+            StakingAction::Stake {
+                pool: 0,
+                amount: 1000,
+            },
+        );
+        assert!(res.contains(&(
+            staker,
+            Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+        )));
+        share_token.balance(staker).contains(1000);
+    }
+
+    sys.spend_blocks(2);
+
+    // The stake follows the shares once both holders are synced
+    share_token.transfer(5, 6, 500);
+
+    for (account, balance) in [(5, 500), (6, 1500)] {
+        let res = st.send(
+            7,
+            StakingAction::SyncShares {
+                pool: 0,
+                account: account.into(),
+            },
+        );
+        assert!(res.contains(&(
+            7,
+            Ok::<StakingEvent, Error>(StakingEvent::SharesSynced {
+                account: account.into(),
+                balance,
+            })
+            .encode()
+        )));
+    }
+
+    sys.spend_blocks(1);
+
+    for (staker, reward) in [(5, 125), (6, 175)] {
+        let res = st.send(
+            staker,
+            StakingAction::GetReward {
+                pool: 0,
+                token: None,
+            },
+        );
+        assert!(res.contains(&(
+            staker,
+            Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), reward)]))
+                .encode()
+        )));
+    }
+
+    share_token.approve(6, st.id().into_bytes(), 1500);
+    let res = st.send(
+        6,
+        StakingAction::Withdraw {
+            pool: 0,
+            amount: 1500,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::Withdrawn {
+            amount: 1500,
+            penalty: 0,
+        })
+        .encode()
+    )));
+    share_token.balance(6).contains(0);
+    st_token.balance(6).contains(20500);
+}
+
+#[test]
+fn vault() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    fund_rewards(&st, &mut st_token, PROGRAMS[1].into(), 1000);
+
+    let res = st.send(
+        4,
+        StakingAction::CreatePool(InitStaking {
+            staking_token_address: PROGRAMS[1].into(),
+            reward_token_address: PROGRAMS[1].into(),
+            distribution_time: 10000,
+            reward_total: 1000,
+            curve: EmissionCurve::Linear,
+            staking_token_kind: TokenKind::Sharded,
+            reward_token_kind: TokenKind::Sharded,
+        }),
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PoolCreated(1)).encode()
+    )));
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::VaultDeposit {
+            pool: 1,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::VaultDeposited {
+            amount: 1000,
+            shares: 1000,
+        })
+        .encode()
+    )));
+
+    sys.spend_blocks(1);
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[1];
+    assert_eq!(pool.vault.price_per_share, PRICE_SCALE * 11 / 10);
+
+    // The reward raises the price, so the same shares cost more
+    st_token.approve(6, st.id().into_bytes(), 1100);
+    let res = st.send(
+        6,
+        StakingAction::VaultDeposit {
+            pool: 1,
+            amount: 1100,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::VaultDeposited {
+            amount: 1100,
+            shares: 1000,
+        })
+        .encode()
+    )));
+
+    sys.spend_blocks(1);
+
+    let res = st.send(
+        5,
+        StakingAction::VaultWithdraw {
+            pool: 1,
+            shares: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::VaultWithdrawn {
+            shares: 1000,
+            amount: 1150,
+            penalty: 0,
+        })
+        .encode()
+    )));
+    st_token.balance(5).contains(10150);
+
+    // Both conversions round down in favour of the vault
+    st_token.approve(7, st.id().into_bytes(), 3);
+    let res = st.send(7, StakingAction::VaultDeposit { pool: 1, amount: 3 });
+    assert!(res.contains(&(
+        7,
+        Ok::<StakingEvent, Error>(StakingEvent::VaultDeposited {
+            amount: 3,
+            shares: 2,
+        })
+        .encode()
+    )));
+
+    let res = st.send(7, StakingAction::VaultWithdraw { pool: 1, shares: 2 });
+    assert!(res.contains(&(
+        7,
+        Ok::<StakingEvent, Error>(StakingEvent::VaultWithdrawn {
+            shares: 2,
+            amount: 2,
+            penalty: 0,
+        })
+        .encode()
+    )));
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[1];
+    assert_eq!(pool.vault.total_shares, 1000);
+    assert_eq!(pool.vault.total_assets, 1151);
+    assert_eq!(pool.vault.shares, vec![(6.into(), 1000)]);
+}
+
+#[test]
+fn vault_penalty_and_unbonding() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    fund_rewards(&st, &mut st_token, PROGRAMS[1].into(), 1000);
+
+    let res = st.send(
+        4,
+        StakingAction::CreatePool(InitStaking {
+            staking_token_address: PROGRAMS[1].into(),
+            reward_token_address: PROGRAMS[1].into(),
+            distribution_time: 10000,
+            reward_total: 1000,
+            curve: EmissionCurve::Linear,
+            staking_token_kind: TokenKind::Sharded,
+            reward_token_kind: TokenKind::Sharded,
+        }),
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PoolCreated(1)).encode()
+    )));
+
+    // The vault can't unbond, so it takes no deposits
+    let res = st.send(
+        4,
+        StakingAction::SetUnbondingPeriod {
+            pool: 1,
+            period: 2000,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::UnbondingPeriodUpdated).encode()
+    )));
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::VaultDeposit {
+            pool: 1,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Err::<StakingEvent, Error>(Error::UnbondingRequired).encode()
+    )));
+
+    let res = st.send(4, StakingAction::SetUnbondingPeriod { pool: 1, period: 0 });
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::UnbondingPeriodUpdated).encode()
+    )));
+
+    let res = st.send(
+        5,
+        StakingAction::VaultDeposit {
+            pool: 1,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::VaultDeposited {
+            amount: 1000,
+            shares: 1000,
+        })
+        .encode()
+    )));
+
+    // Nor can the deposits be trapped by a new unbonding period
+    let res = st.send(
+        4,
+        StakingAction::SetUnbondingPeriod {
+            pool: 1,
+            period: 2000,
+        },
+    );
+    assert!(res.contains(&(4, Err::<StakingEvent, Error>(Error::PoolNotEmpty).encode())));
+
+    let res = st.send(
+        4,
+        StakingAction::SetPenalty {
+            pool: 1,
+            config: Some(PenaltyConfig {
+                rate: 1000,
+                holding_period: 10000,
+                destination: PenaltyDestination::Treasury(7.into()),
+            }),
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PenaltyUpdated).encode()
+    )));
+
+    // The early withdrawal from the vault is charged like any other
+    let res = st.send(
+        5,
+        StakingAction::VaultWithdraw {
+            pool: 1,
+            shares: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::VaultWithdrawn {
+            shares: 1000,
+            amount: 1000,
+            penalty: 100,
+        })
+        .encode()
+    )));
+
+    st_token.balance(5).contains(9900);
+    st_token.balance(7).contains(20100);
+
+    // The vault only holds the stake deposited through it
+    st_token.approve(6, st.id().into_bytes(), 1000);
+    let res = st.send(
+        6,
+        StakingAction::StakeFor {
+            pool: 1,
+            beneficiary: vault_key(),
+            amount: 1000,
+            locked_until: None,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Err::<StakingEvent, Error>(Error::ReservedAddress).encode()
+    )));
+}
+
+#[test]
+fn vault_deposit_times() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    fund_rewards(&st, &mut st_token, PROGRAMS[1].into(), 1000);
+
+    let res = st.send(
+        4,
+        StakingAction::CreatePool(InitStaking {
+            staking_token_address: PROGRAMS[1].into(),
+            reward_token_address: PROGRAMS[1].into(),
+            distribution_time: 10000,
+            reward_total: 1000,
+            curve: EmissionCurve::Linear,
+            staking_token_kind: TokenKind::Sharded,
+            reward_token_kind: TokenKind::Sharded,
+        }),
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PoolCreated(1)).encode()
+    )));
+
+    let res = st.send(
+        4,
+        StakingAction::SetPenalty {
+            pool: 1,
+            config: Some(PenaltyConfig {
+                rate: 1000,
+                holding_period: 10000,
+                destination: PenaltyDestination::Treasury(7.into()),
+            }),
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PenaltyUpdated).encode()
+    )));
+
+    let res = st.send(
+        4,
+        StakingAction::SetTiers {
+            pool: 1,
+            tiers: vec![(2000, 20000)],
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::TiersUpdated).encode()
+    )));
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::VaultDeposit {
+            pool: 1,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::VaultDeposited {
+            amount: 1000,
+            shares: 1000,
+        })
+        .encode()
+    )));
+
+    sys.spend_blocks(10);
+
+    st_token.approve(6, st.id().into_bytes(), 1000);
+    let res = st.send(
+        6,
+        StakingAction::VaultDeposit {
+            pool: 1,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::VaultDeposited {
+            amount: 1000,
+            shares: 500,
+        })
+        .encode()
+    )));
+
+    // The vault's combined balance doesn't reach a tier
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[1];
+    let stakers: HashMap<_, _> = pool.stakers.iter().cloned().collect();
+    assert_eq!(stakers[&vault_key()].balance, 3000);
+    assert_eq!(stakers[&vault_key()].tier_weight, 0);
+    assert_eq!(pool.total_staked, 3000);
+    assert_eq!(pool.vault.deposited_at.len(), 2);
+
+    // The new deposit doesn't restart the holding period of the earlier one
+    let res = st.send(
+        5,
+        StakingAction::VaultWithdraw {
+            pool: 1,
+            shares: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::VaultWithdrawn {
+            shares: 1000,
+            amount: 2000,
+            penalty: 0,
+        })
+        .encode()
+    )));
+
+    let res = st.send(
+        6,
+        StakingAction::VaultWithdraw {
+            pool: 1,
+            shares: 500,
+        },
+    );
+    assert!(res.contains(&(
+        6,
+        Ok::<StakingEvent, Error>(StakingEvent::VaultWithdrawn {
+            shares: 500,
+            amount: 1000,
+            penalty: 100,
+        })
+        .encode()
+    )));
+
+    st_token.balance(5).contains(11000);
+    st_token.balance(6).contains(19900);
+    st_token.balance(7).contains(20100);
+}
+
+#[test]
+fn native_staking() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut rw_token = init_reward_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    let amount = 10_u128.pow(13);
+    fund_rewards(&st, &mut rw_token, reward_token(), 1000);
+
+    let res = st.send(
+        4,
+        StakingAction::CreatePool(InitStaking {
+            staking_token_address: ActorId::zero(),
+            reward_token_address: PROGRAMS[2].into(),
+            distribution_time: 10000,
+            reward_total: 1000,
+            curve: EmissionCurve::Linear,
+            staking_token_kind: TokenKind::Native,
+            reward_token_kind: TokenKind::Sharded,
+        }),
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PoolCreated(1)).encode()
+    )));
+
+    sys.mint_to(5, amount);
+    let res = st.send_with_value(5, StakingAction::Stake { pool: 1, amount }, amount);
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(amount)).encode()
+    )));
+    assert_eq!(sys.balance_of(5), 0);
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[1];
+    assert_eq!(
+        state.token_kinds[&pool.staking_token_address],
+        TokenKind::Native
+    );
+    assert_eq!(state.token_kinds[&pool.rewards[0].0], TokenKind::Sharded);
+
+    sys.spend_blocks(1);
+
+    let res = st.send(5, StakingAction::Withdraw { pool: 1, amount });
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Withdrawn { amount, penalty: 0 }).encode()
+    )));
+
+    // The value comes back with a message in the mailbox
+    sys.claim_value_from_mailbox(5);
+    assert_eq!(sys.balance_of(5), amount);
+
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 1,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(reward_token(), 100)])).encode()
+    )));
+}
+
+#[test]
+fn native_rewards() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut st_token = init_staking_token(&sys);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    // The value can be funded before any pool pays it
+    let amount = 10_u128.pow(14);
+    sys.mint_to(4, amount);
+    let res = st.send_with_value(
+        4,
+        StakingAction::FundRewards {
+            token: ActorId::zero(),
+            kind: TokenKind::Native,
+            amount,
+        },
+        amount,
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::RewardsFunded {
+            token: ActorId::zero(),
+            amount,
+        })
+        .encode()
+    )));
+
+    let res = st.send(
+        4,
+        StakingAction::CreatePool(InitStaking {
+            staking_token_address: PROGRAMS[1].into(),
+            reward_token_address: ActorId::zero(),
+            distribution_time: 10000,
+            reward_total: amount,
+            curve: EmissionCurve::Linear,
+            staking_token_kind: TokenKind::Sharded,
+            reward_token_kind: TokenKind::Native,
+        }),
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PoolCreated(1)).encode()
+    )));
+
+    st_token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 1,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+
+    sys.spend_blocks(1);
+
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 1,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(ActorId::zero(), amount / 10)]))
+            .encode()
+    )));
+
+    sys.claim_value_from_mailbox(5);
+    assert_eq!(sys.balance_of(5), amount / 10);
+}
+
+#[test]
+fn fungible_token_adapter() {
+    let sys = System::new();
+    init_staking(&sys);
+    let mut token = LegacyFungibleToken::initialize_with_id(&sys, LEGACY_TOKEN);
+    sys.init_logger();
+    let st = sys.get_program(1);
+
+    token.mint(4, 1000);
+    token.approve(4, st.id().into_bytes(), 1000);
+    let res = st.send(
+        4,
+        StakingAction::FundRewards {
+            token: LEGACY_TOKEN.into(),
+            kind: TokenKind::Fungible,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::RewardsFunded {
+            token: LEGACY_TOKEN.into(),
+            amount: 1000,
+        })
+        .encode()
+    )));
+
+    let res = st.send(
+        4,
+        StakingAction::CreatePool(InitStaking {
+            staking_token_address: LEGACY_TOKEN.into(),
+            reward_token_address: LEGACY_TOKEN.into(),
+            distribution_time: 10000,
+            reward_total: 1000,
+            curve: EmissionCurve::Linear,
+            staking_token_kind: TokenKind::Fungible,
+            reward_token_kind: TokenKind::Fungible,
+        }),
+    );
+    assert!(res.contains(&(
+        4,
+        Ok::<StakingEvent, Error>(StakingEvent::PoolCreated(1)).encode()
+    )));
+
+    token.mint(5, 1000);
+    token.approve(5, st.id().into_bytes(), 1000);
+    let res = st.send(
+        5,
+        StakingAction::Stake {
+            pool: 1,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::StakeAccepted(1000)).encode()
+    )));
+    token.balance(5).contains(0);
+
+    sys.spend_blocks(1);
+
+    let res = st.send(
+        5,
+        StakingAction::GetReward {
+            pool: 1,
+            token: None,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Reward(vec![(LEGACY_TOKEN.into(), 100)])).encode()
+    )));
+
+    let res = st.send(
+        5,
+        StakingAction::Withdraw {
+            pool: 1,
+            amount: 1000,
+        },
+    );
+    assert!(res.contains(&(
+        5,
+        Ok::<StakingEvent, Error>(StakingEvent::Withdrawn {
+            amount: 1000,
+            penalty: 0,
+        })
+        .encode()
+    )));
+    token.balance(5).contains(1100);
+
+    let state: IoStaking = st.read_state().expect("Can't read state");
+    let (_id, pool) = &state.pools[1];
+    assert_eq!(
+        state.token_kinds[&pool.staking_token_address],
+        TokenKind::Fungible
+    );
+    assert_eq!(state.token_kinds[&pool.rewards[0].0], TokenKind::Fungible);
+}
@@ -24,6 +24,23 @@ impl<'a> FungibleToken<'a> {
             system,
             "target/wasm32-unknown-unknown/debug/ft_main.opt.wasm",
         );
+
+        Self::init_program(system, program)
+    }
+
+    #[track_caller]
+    pub fn initialize_with_id(system: &'a System, id: u64) -> Self {
+        let program = InnerProgram::from_file_with_id(
+            system,
+            id,
+            "target/wasm32-unknown-unknown/debug/ft_main.opt.wasm",
+        );
+
+        Self::init_program(system, program)
+    }
+
+    #[track_caller]
+    fn init_program(system: &'a System, program: InnerProgram<'a>) -> Self {
         let storage_code_id: [u8; 32] = system
             .submit_code("target/wasm32-unknown-unknown/debug/ft_storage.opt.wasm")
             .into();
@@ -76,6 +93,23 @@ impl<'a> FungibleToken<'a> {
         ));
     }
 
+    #[track_caller]
+    pub fn transfer(&mut self, from: u64, recipient: impl Into<ActorId>, amount: u128) {
+        let transaction_id = self.transaction_id();
+
+        assert_ft_token_event_ok(self.0.send(
+            from,
+            FTokenAction::Message {
+                transaction_id,
+                payload: LogicAction::Transfer {
+                    sender: from.into(),
+                    recipient: recipient.into(),
+                    amount,
+                },
+            },
+        ));
+    }
+
     pub fn balance(&self, actor_id: impl Into<ActorId>) -> RunResult<u128, FTokenEvent, ()> {
         RunResult::new(
             self.0
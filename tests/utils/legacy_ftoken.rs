@@ -0,0 +1,64 @@
+use super::{Program, RunResult, FOREIGN_USER};
+use ft_io::{FTAction, FTEvent, InitConfig};
+use gstd::{prelude::*, ActorId};
+use gtest::{Program as InnerProgram, System};
+
+/// The gear fungible token, which isn't sharded
+pub struct LegacyFungibleToken<'a>(InnerProgram<'a>);
+
+impl Program for LegacyFungibleToken<'_> {
+    fn inner_program(&self) -> &InnerProgram {
+        &self.0
+    }
+}
+
+impl<'a> LegacyFungibleToken<'a> {
+    #[track_caller]
+    pub fn initialize_with_id(system: &'a System, id: u64) -> Self {
+        let program = InnerProgram::from_file_with_id(
+            system,
+            id,
+            "target/wasm32-unknown-unknown/debug/fungible_token.opt.wasm",
+        );
+
+        assert!(!program
+            .send(
+                FOREIGN_USER,
+                InitConfig {
+                    name: "Legacy".into(),
+                    symbol: "LFT".into(),
+                    decimals: 18,
+                },
+            )
+            .main_failed());
+
+        Self(program)
+    }
+
+    #[track_caller]
+    pub fn mint(&mut self, recipient: u64, amount: u128) {
+        assert!(!self.0.send(recipient, FTAction::Mint(amount)).main_failed());
+    }
+
+    #[track_caller]
+    pub fn approve(&mut self, from: u64, approved_account: impl Into<ActorId>, amount: u128) {
+        assert!(!self
+            .0
+            .send(
+                from,
+                FTAction::Approve {
+                    to: approved_account.into(),
+                    amount,
+                },
+            )
+            .main_failed());
+    }
+
+    pub fn balance(&self, actor_id: impl Into<ActorId>) -> RunResult<u128, FTEvent, ()> {
+        RunResult::new(
+            self.0
+                .send(FOREIGN_USER, FTAction::BalanceOf(actor_id.into())),
+            FTEvent::Balance,
+        )
+    }
+}
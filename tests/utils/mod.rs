@@ -1,6 +1,9 @@
 mod ftoken;
 pub use ftoken::*;
 
+mod legacy_ftoken;
+pub use legacy_ftoken::*;
+
 mod common;
 pub use common::*;
 